@@ -1,14 +1,15 @@
 use std::sync::Arc;
 use log::{info, debug, error};
 use tokio_tungstenite::{connect_async, WebSocketStream, MaybeTlsStream, tungstenite::protocol::Message};
-use tokio::{net::TcpStream, sync::{mpsc::UnboundedSender, Mutex}};  
+use tokio::{net::TcpStream, sync::{broadcast, mpsc::UnboundedSender, Mutex, RwLock}};  
 use serde_derive::{Deserialize, Serialize};
 use futures::{ stream::{SplitSink, SplitStream}, SinkExt, StreamExt };
 use eyre::{eyre, Result}; 
 use tokio_tungstenite::tungstenite;
 use reqwest;
 use chrono::prelude::*;
-use crate::{env::ENV, ws_structs::*};
+use rust_decimal::Decimal;
+use crate::{batch::CancelFilter, credentials::Secret, env::ENV, instruments::{to_decimal, Instruments}, kill_switch::{ConnectionStatus, KillSwitch}, mmp::MmpTracker, order_manager::{OrderManager, Submission}, paper::{PaperOrderParams, PaperTrading}, positions::PositionTracker, rate_limit::{RateLimitClass, RateLimiter}, recorder::Recorder, replies::PendingReplies, retry::RetryPolicy, risk::{RiskManager, RiskReservation}, types::Timestamp, ws_structs::*};
 
 #[derive(Debug)]
 pub struct AevoClient {
@@ -17,19 +18,62 @@ pub struct AevoClient {
     pub reader: Arc<Mutex<Option<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>>>,
     pub client : reqwest::Client, 
     pub env : ENV,
+    pub instruments : Arc<RwLock<Instruments>>,
+    pub orders : Arc<Mutex<OrderManager>>,
+    pub positions : Arc<Mutex<PositionTracker>>,
+    pub risk : Arc<Mutex<RiskManager>>,
+    pub kill_switch : Arc<KillSwitch>,
+    pub connection : Arc<ConnectionStatus>,
+    pub rate_limiter : Arc<RateLimiter>,
+    pub retry_policy : RetryPolicy,
+    pub replies : Arc<PendingReplies>,
+    pub mmp : Arc<Mutex<MmpTracker>>,
+    // Set in paper trading mode, see enable_paper_trading
+    pub paper : Option<Arc<PaperTrading>>,
+    // Set with set_recorder
+    pub recorder : Option<Recorder>,
+    // Signing key registered by rotate_signing_key, used in place of the credentials' one
+    pub rotated_key : Arc<std::sync::RwLock<Option<Secret>>>,
+    // Every decoded message, for components that need market data next to the caller's read loop
+    pub events : broadcast::Sender<WsResponse>,
 }
 
-#[derive(Debug)]
+// See credentials.rs for loading
+#[derive(Debug, Clone)]
 pub struct ClientCredentials {
-    pub signing_key : String, 
+    pub signing_key : Secret, 
     pub wallet_address : String, 
-    pub wallet_private_key : Option<String>, 
+    // Only needed to register signing keys, see registration.rs
+    pub wallet_private_key : Option<Secret>, 
     pub api_key : String, 
-    pub api_secret : String, 
+    pub api_secret : Secret, 
 }
 
 pub const PRICE_DECIMALS: u32 = 6; 
 pub const AMOUNT_DECIMALS: u32 = 6;
+const EVENTS_CAPACITY: usize = 1024;
+
+// Scales a price or amount into the integer units used in signed payloads. Goes through a decimal
+// so values like 0.57 are not truncated to 569999 by floating point error.
+pub fn to_fixed(value: f64, decimals: u32) -> Result<String> {
+    let scaled = to_decimal(value)? * Decimal::from(10_u64.pow(decimals)); 
+    Ok(scaled.floor().to_string())
+}
+
+pub fn from_fixed(value: &str, decimals: u32) -> Result<Decimal> {
+    let scaled: Decimal = value.parse()?; 
+    Ok(scaled / Decimal::from(10_u64.pow(decimals)))
+}
+
+// Errors after which the websocket yields nothing more and has to be reconnected, as opposed to a
+// single bad message
+pub fn ends_stream(e: &tungstenite::Error) -> bool {
+    matches!(
+        e,
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed | tungstenite::Error::Io(_)
+            | tungstenite::Error::Tls(_) | tungstenite::Error::Protocol(_)
+    )
+}
 
 impl AevoClient {
     pub async fn new(
@@ -42,7 +86,21 @@ impl AevoClient {
             writer : Arc::new(Mutex::new(None)), 
             reader : Arc::new(Mutex::new(None)),
             client : reqwest::Client::new(), 
-            env: env
+            env,
+            instruments : Arc::new(RwLock::new(Instruments::new())),
+            orders : Arc::new(Mutex::new(OrderManager::new())),
+            positions : Arc::new(Mutex::new(PositionTracker::new())),
+            risk : Arc::new(Mutex::new(RiskManager::default())),
+            kill_switch : Arc::new(KillSwitch::new()),
+            connection : Arc::new(ConnectionStatus::new()),
+            rate_limiter : Arc::new(RateLimiter::default()),
+            retry_policy : RetryPolicy::default(),
+            replies : Arc::new(PendingReplies::new()),
+            mmp : Arc::new(Mutex::new(MmpTracker::new())),
+            paper : None,
+            recorder : None,
+            rotated_key : Arc::new(std::sync::RwLock::new(None)),
+            events : broadcast::channel(EVENTS_CAPACITY).0
         }; 
 
         let ws_stream = client.open_connection().await?; 
@@ -69,13 +127,13 @@ impl AevoClient {
 
                 let auth_request = WsRequest {
                     op : "auth".to_string(),
-                    data : WsRequestData::AuthData { key: credentials.api_key.to_string(), secret: credentials.api_secret.to_string() },
+                    data : WsRequestData::AuthData { key: credentials.api_key.to_string(), secret: credentials.api_secret.clone() },
                     id : Some(1)
                 }; 
 
                 let auth_msg = Message::from(serde_json::to_string(&auth_request)?); 
 
-                debug!("The auth message: {:?}", auth_msg); 
+                debug!("Authenticating with api key {}", credentials.api_key); 
 
                 ws_stream.send(auth_msg).await?;
             }, 
@@ -121,51 +179,136 @@ impl AevoClient {
             let mut reader_guard = self.reader.lock().await;
             *reader_guard = Some(reader);
         }
+        self.connection.mark_connected();
+
+        // Order updates sent while disconnected were missed
+        if self.credentials.is_some() {
+            if let Err(e) = self.reconcile_orders().await {
+                error!("Problem reconciling orders after reconnect: {}", e); 
+            }
+        }
 
         Ok(())
     }
 
     pub async fn read_messages(&self, tx : UnboundedSender<WsResponse>) -> Result<()> {
         loop {
-            let msg = {
-                let mut reader_guard = self.reader.lock().await; 
-                match reader_guard.as_mut() {
-                    Some(ws_stream) => {
-                        ws_stream.next().await
+            let response = tokio::select! {
+                msg = self.next_message() => match msg? {
+                    Some(msg) => match AevoClient::parse_response(msg) {
+                        Ok(response) => {
+                            self.record(&response, Utc::now()); 
+                            response
+                        }, 
+                        Err(e) => {
+                            error!("Problem parsing the response: {}", e); 
+                            continue;
+                        }
                     }, 
-                    None => {
-                        return Err(eyre!("No connection is set"))
-                    }
-                }
+                    None => continue
+                }, 
+                Some(response) = self.next_paper_message() => response
             }; 
 
-            match msg {
-                Some(Ok(msg)) => {
-                    match AevoClient::parse_response(msg) {
+            self.dispatch(&response).await; 
+            if let Err(e) = tx.send(response) {
+                error!("Problem sending data through unbounded channel: {}", e); 
+            }
+        }
+    }
+
+    // Same as read_messages but keeps the exact text received from the exchange next to the decoded response
+    pub async fn read_raw_messages(&self, tx : UnboundedSender<RawWsResponse>) -> Result<()> {
+        loop {
+            let response = tokio::select! {
+                msg = self.next_message() => match msg? {
+                    Some(msg) => match AevoClient::parse_raw_response(msg) {
                         Ok(response) => {
-                            match tx.send(response) {
-                                Err(e) => error!("Problem sending data through unbounded channel: {}", e), 
-                                _ => {}
-                            };
+                            self.record(&response.response, response.received); 
+                            response
                         }, 
-                        Err(e) => error!("Problem parsing the response: {}", e)
-                    }
- 
-                }, 
-                Some(Err(e)) => {
-                    match e {
-                        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
-                            info!("Aevo websocket connection close with error : {}", e);
-                            
-                            self.reconnect().await?; 
-                        },
-                        _ => {
-                            error!("Message reading error : {}", e);
+                        Err(e) => {
+                            error!("Problem parsing the response: {}", e); 
+                            continue;
                         }
-                    }
-                    error!("Error reading message: {}", e);
-                },
-                None => {}
+                    }, 
+                    None => continue
+                }, 
+                // Paper trading messages were never on the wire, their raw text is the serialized response
+                Some(response) = self.next_paper_message() => RawWsResponse { 
+                    raw: serde_json::to_string(&response)?, 
+                    received: Utc::now(), 
+                    response 
+                }
+            }; 
+
+            self.dispatch(&response.response).await; 
+            if let Err(e) = tx.send(response) {
+                error!("Problem sending data through unbounded channel: {}", e); 
+            }
+        }
+    }
+
+    // Only messages read from the websocket are recorded, not the ones of the paper exchange
+    fn record(&self, response: &WsResponse, received: Timestamp) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(response, received); 
+        }
+    }
+
+    // Feeds a received message to the trackers, the pending replies and the event subscribers
+    async fn dispatch(&self, response: &WsResponse) {
+        self.orders.lock().await.apply(response); 
+        self.positions.lock().await.apply(response); 
+        self.risk.lock().await.apply(response); 
+        self.mmp.lock().await.apply(response); 
+        let _ = self.events.send(response.clone()); 
+        self.replies.resolve(response); 
+
+        if let Some(paper) = &self.paper {
+            let messages = paper.exchange.lock().await.apply_market(response); 
+            paper.publish(messages); 
+        }
+    }
+
+    async fn next_paper_message(&self) -> Option<WsResponse> {
+        match &self.paper {
+            Some(paper) => paper.next().await, 
+            None => std::future::pending().await
+        }
+    }
+
+    async fn next_message(&self) -> Result<Option<Message>> {
+        let msg = {
+            let mut reader_guard = self.reader.lock().await; 
+            match reader_guard.as_mut() {
+                Some(ws_stream) => {
+                    ws_stream.next().await
+                }, 
+                None => {
+                    return Err(eyre!("No connection is set"))
+                }
+            }
+        }; 
+
+        match msg {
+            Some(Ok(msg)) => Ok(Some(msg)), 
+            Some(Err(e)) if ends_stream(&e) => {
+                info!("Aevo websocket connection close with error : {}", e);
+                self.connection.mark_disconnected();
+                self.reconnect().await?; 
+                Ok(None)
+            },
+            Some(Err(e)) => {
+                error!("Message reading error : {}", e);
+                Ok(None)
+            },
+            // The stream only ends once the connection is gone, reading on would spin
+            None => {
+                info!("Aevo websocket stream ended");
+                self.connection.mark_disconnected();
+                self.reconnect().await?; 
+                Ok(None)
             }
         }
     }
@@ -175,9 +318,17 @@ impl AevoClient {
         serde_json::from_str::<WsResponse>(&msg_txt).map_err(|e| eyre!("Error : {}; Message : {}", e, msg_txt))
     }
 
+    pub fn parse_raw_response(msg : Message) -> Result<RawWsResponse> {
+        let received = Utc::now(); 
+        let msg_txt = msg.into_text()?; 
+        let response = serde_json::from_str::<WsResponse>(&msg_txt).map_err(|e| eyre!("Error : {}; Message : {}", e, msg_txt))?;
+        Ok(RawWsResponse { raw: msg_txt, received, response })
+    }
+
     pub async fn send (&self, data: &Message) -> Result<()>{
         let mut attempts = 0; 
         const MAX_ATTEMPTS: u8 = 2; 
+        self.rate_limiter.acquire(RateLimitClass::of_ws_message(data)).await;
         while attempts < MAX_ATTEMPTS {
             let result = {
                 let mut writer_guard = self.writer.lock().await; 
@@ -195,9 +346,10 @@ impl AevoClient {
                 Ok(_) => return Ok(()),
                 Err(e) => {
                     match e  {
-                        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => {
+                        e if ends_stream(&e) => {
                             if attempts == 0 {
                                 info!("Aevo websocket connection close with error : {}", e);
+                                self.connection.mark_disconnected();
                                 self.reconnect().await?;
                                 attempts += 1; 
                                 continue; 
@@ -263,7 +415,7 @@ impl AevoClient {
     pub async fn subscribe_trades(&self, instrument_name: String) -> Result<()> {
         let request = WsRequest {
             op : "subscribe".to_string(),
-            data : WsRequestData::ChannelData(vec![format!("orderbook:{}", instrument_name)]), 
+            data : WsRequestData::ChannelData(vec![format!("trades:{}", instrument_name)]), 
             id: None
         };
 
@@ -322,6 +474,24 @@ impl AevoClient {
         post_only: Option<bool>,
         mmp: Option<bool>,
     ) -> Result<(WsRequestData, String)>{
+        let (data, order_id, _) = self.reserved_order_ws(instrument_id, is_buy, limit_price, quantity, post_only, mmp).await?; 
+        Ok((data, order_id))
+    }
+
+    // create_order_ws keeping the risk reservation, to be dropped once the order is recorded
+    async fn reserved_order_ws (
+        &self, 
+        instrument_id: u64, 
+        is_buy: bool, 
+        limit_price: f64, 
+        quantity: f64, 
+        post_only: Option<bool>,
+        mmp: Option<bool>,
+    ) -> Result<(WsRequestData, String, RiskReservation)>{
+        let (rounded_price, quantity) = self.prepare_order(instrument_id, is_buy, Some(limit_price), quantity).await?; 
+        let limit_price = rounded_price.unwrap_or(limit_price); 
+        let reservation = self.check_risk(instrument_id, is_buy, Some(limit_price), quantity, None).await?; 
+
         let timestamp = Utc::now().timestamp(); 
         let (salt, signature, order_id) = self.sign_order(
             instrument_id, 
@@ -340,8 +510,8 @@ impl AevoClient {
             maker : wallet_address, 
             is_buy: is_buy, 
             instrument: instrument_id.to_string(), 
-            limit_price : (limit_price * 10_i32.pow(PRICE_DECIMALS) as f64).floor().to_string(), 
-            amount : (quantity * 10_i32.pow(AMOUNT_DECIMALS) as f64).floor().to_string(), 
+            limit_price : to_fixed(limit_price, PRICE_DECIMALS)?, 
+            amount : to_fixed(quantity, AMOUNT_DECIMALS)?, 
             salt : salt.to_string(), 
             signature : signature, 
             post_only : match post_only {
@@ -355,7 +525,7 @@ impl AevoClient {
             timestamp : timestamp.to_string(),
         }; 
 
-        Ok((payload, order_id))
+        Ok((payload, order_id, reservation))
     }
 
     pub async fn create_order(
@@ -368,9 +538,35 @@ impl AevoClient {
         id: Option<u64>, 
         mmp: Option<bool>
     ) -> Result<String>{
+        // Error replies are matched to orders by request id, so every order is sent with one
+        let id = Some(id.unwrap_or_else(|| self.replies.next_id()));
+        if self.paper.is_some() {
+            let params = PaperOrderParams {
+                replaces : None,
+                instrument_id,
+                is_buy,
+                limit_price : Some(limit_price),
+                quantity,
+                post_only : post_only.unwrap_or(true),
+                ioc : false
+            };
+            return self.paper_create_order(params, id).await
+        }
 
-        let (data, order_id) = self.create_order_ws(instrument_id, is_buy, limit_price, quantity, post_only, mmp).await?;
-
+        let (data, order_id, reservation) = self.reserved_order_ws(instrument_id, is_buy, limit_price, quantity, post_only, mmp).await?;
+
+        if let WsRequestData::OrderData { limit_price, amount, .. } = &data {
+            self.orders.lock().await.record_submission(Submission {
+                order_id : order_id.clone(), 
+                instrument_id, 
+                is_buy, 
+                price : Some(from_fixed(limit_price, PRICE_DECIMALS)?), 
+                amount : from_fixed(amount, AMOUNT_DECIMALS)?, 
+                request_id : id, 
+                replaces : None
+            }); 
+        }
+        drop(reservation); 
 
         let request = WsRequest {
             op : "create_order".to_string(), 
@@ -381,8 +577,10 @@ impl AevoClient {
         info!("Order created: {:?}", request.data); 
 
         let msg = Message::from(serde_json::to_string(&request)?); 
-        self.send(&msg).await?;
-
+        if let Err(e) = self.send(&msg).await {
+            self.orders.lock().await.mark_rejected(&order_id, &e.to_string()); 
+            return Err(e)
+        }
 
         Ok(order_id)
     }
@@ -398,6 +596,24 @@ impl AevoClient {
         post_only: Option<bool>,
         mmp: Option<bool>,
     ) -> Result<String>{
+        let id = Some(id.unwrap_or_else(|| self.replies.next_id()));
+        if self.paper.is_some() {
+            let params = PaperOrderParams {
+                replaces : Some(&order_id),
+                instrument_id,
+                is_buy,
+                limit_price : Some(limit_price),
+                quantity,
+                post_only : post_only.unwrap_or(true),
+                ioc : false
+            };
+            return self.paper_create_order(params, id).await
+        }
+
+        let (rounded_price, quantity) = self.prepare_order(instrument_id, is_buy, Some(limit_price), quantity).await?; 
+        let limit_price = rounded_price.unwrap_or(limit_price); 
+        let reservation = self.check_risk(instrument_id, is_buy, Some(limit_price), quantity, Some(&order_id)).await?; 
+
         let timestamp = Utc::now().timestamp();
         let (salt, signature, new_order_id) = self.sign_order(
             instrument_id, 
@@ -412,6 +628,17 @@ impl AevoClient {
             None => return Err(eyre!("Order sign error: Wallet address not set"))
         };
 
+        self.orders.lock().await.record_submission(Submission {
+            order_id : new_order_id.clone(), 
+            instrument_id, 
+            is_buy, 
+            price : Some(to_decimal(limit_price)?), 
+            amount : to_decimal(quantity)?, 
+            request_id : id, 
+            replaces : Some(order_id.clone())
+        }); 
+        drop(reservation); 
+
         let request = WsRequest {
             op : "edit_order".to_string(), 
             data : WsRequestData::EditOrderData { 
@@ -419,8 +646,8 @@ impl AevoClient {
                 maker: wallet_address, 
                 is_buy: is_buy, 
                 instrument: instrument_id.to_string(), 
-                limit_price: (limit_price * 10_i32.pow(6 as u32) as f64).floor().to_string(), 
-                amount: (quantity * 10_i32.pow(6 as u32) as f64).floor().to_string(), 
+                limit_price: to_fixed(limit_price, PRICE_DECIMALS)?, 
+                amount: to_fixed(quantity, AMOUNT_DECIMALS)?, 
                 salt: salt.to_string(), 
                 signature: signature, 
                 post_only: match post_only {
@@ -439,12 +666,19 @@ impl AevoClient {
         info!("Order edited: {:?}", request.data); 
         
         let msg = Message::from(serde_json::to_string(&request)?); 
-        self.send(&msg).await?;
+        if let Err(e) = self.send(&msg).await {
+            self.orders.lock().await.mark_rejected(&new_order_id, &e.to_string()); 
+            return Err(e)
+        }
 
         Ok(new_order_id)
     }
 
     pub async fn cancel_order(&self, order_id : String) -> Result<()>{
+        if self.paper.is_some() {
+            return self.paper_cancel_order(&order_id).await
+        }
+
         let request = WsRequest{
             op: "cancel_order".to_string(), 
             data: WsRequestData::CancelOrderData { order_id: order_id },
@@ -459,9 +693,13 @@ impl AevoClient {
     }
 
     pub async fn cancel_all_orders(&self) -> Result<()> {
+        if self.paper.is_some() {
+            return self.paper_cancel_all_orders(&CancelFilter::default()).await.map(|_| ())
+        }
+
         let request = WsRequest{
             op: "cancel_all_orders".to_string(), 
-            data: WsRequestData::CancelAllOrdersData { },
+            data: WsRequestData::CancelAllOrdersData { instrument_type: None, asset: None, instrument: None },
             id: None
         }; 
 
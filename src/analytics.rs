@@ -0,0 +1,201 @@
+use std::{f64::consts::{PI, SQRT_2}, ops::Add};
+use eyre::{eyre, Result};
+use rust_decimal::prelude::ToPrimitive;
+use crate::{instruments::OptionKind, rest::MarketInfo, types::Timestamp};
+
+const SECONDS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+const IV_TOLERANCE: f64 = 1e-8;
+const IV_MAX_ITERATIONS: usize = 100;
+const IV_MIN: f64 = 1e-4;
+const IV_MAX: f64 = 10.0;
+
+// Inputs to the Black-Scholes model for a European option. Aevo settles against the index at
+// expiry so passing the forward as `spot` with a zero rate gives the Black-76 price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OptionParams {
+    pub option_kind : OptionKind,
+    pub spot : f64,
+    pub strike : f64,
+    // Time to expiry in years
+    pub time : f64,
+    pub volatility : f64,
+    pub rate : f64
+}
+
+// Greeks in the exchange's conventions: theta per calendar day, vega and rho per 1% move
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ModelGreeks {
+    pub delta : f64,
+    pub gamma : f64,
+    pub theta : f64,
+    pub vega : f64,
+    pub rho : f64
+}
+
+impl ModelGreeks {
+    // Greeks of a position holding `amount` contracts, negative for short positions
+    pub fn scale(&self, amount: f64) -> ModelGreeks {
+        ModelGreeks {
+            delta : self.delta * amount,
+            gamma : self.gamma * amount,
+            theta : self.theta * amount,
+            vega : self.vega * amount,
+            rho : self.rho * amount
+        }
+    }
+}
+
+impl Add for ModelGreeks {
+    type Output = ModelGreeks;
+
+    fn add(self, other: ModelGreeks) -> ModelGreeks {
+        ModelGreeks {
+            delta : self.delta + other.delta,
+            gamma : self.gamma + other.gamma,
+            theta : self.theta + other.theta,
+            vega : self.vega + other.vega,
+            rho : self.rho + other.rho
+        }
+    }
+}
+
+pub fn year_fraction(expiry: Timestamp, now: Timestamp) -> f64 {
+    ((expiry - now).num_milliseconds() as f64 / 1000.0 / SECONDS_PER_YEAR).max(0.0)
+}
+
+pub fn norm_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / SQRT_2)
+}
+
+pub fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * PI).sqrt()
+}
+
+// Complementary error function with fractional error below 1.2e-7 (Numerical Recipes erfcc)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418
+        + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587
+        + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+impl OptionParams {
+    fn intrinsic(&self) -> f64 {
+        let discount = (-self.rate * self.time).exp();
+        match self.option_kind {
+            OptionKind::Call => (self.spot - self.strike * discount).max(0.0),
+            OptionKind::Put => (self.strike * discount - self.spot).max(0.0)
+        }
+    }
+
+    fn d1_d2(&self) -> (f64, f64) {
+        let vol_sqrt_t = self.volatility * self.time.sqrt();
+        let d1 = ((self.spot / self.strike).ln() + (self.rate + 0.5 * self.volatility * self.volatility) * self.time) / vol_sqrt_t;
+        (d1, d1 - vol_sqrt_t)
+    }
+
+    pub fn price(&self) -> f64 {
+        if self.time <= 0.0 || self.volatility <= 0.0 {
+            return self.intrinsic()
+        }
+
+        let (d1, d2) = self.d1_d2();
+        let discount = (-self.rate * self.time).exp();
+        match self.option_kind {
+            OptionKind::Call => self.spot * norm_cdf(d1) - self.strike * discount * norm_cdf(d2),
+            OptionKind::Put => self.strike * discount * norm_cdf(-d2) - self.spot * norm_cdf(-d1)
+        }
+    }
+
+    pub fn greeks(&self) -> ModelGreeks {
+        if self.time <= 0.0 || self.volatility <= 0.0 {
+            let in_the_money = self.intrinsic() > 0.0;
+            let delta = match (self.option_kind, in_the_money) {
+                (OptionKind::Call, true) => 1.0,
+                (OptionKind::Put, true) => -1.0,
+                _ => 0.0
+            };
+            return ModelGreeks { delta, ..ModelGreeks::default() }
+        }
+
+        let (d1, d2) = self.d1_d2();
+        let sqrt_t = self.time.sqrt();
+        let discount = (-self.rate * self.time).exp();
+        let pdf = norm_pdf(d1);
+        let decay = -self.spot * pdf * self.volatility / (2.0 * sqrt_t);
+
+        let (delta, theta, rho) = match self.option_kind {
+            OptionKind::Call => (
+                norm_cdf(d1),
+                decay - self.rate * self.strike * discount * norm_cdf(d2),
+                self.strike * self.time * discount * norm_cdf(d2)
+            ),
+            OptionKind::Put => (
+                norm_cdf(d1) - 1.0,
+                decay + self.rate * self.strike * discount * norm_cdf(-d2),
+                -self.strike * self.time * discount * norm_cdf(-d2)
+            )
+        };
+
+        ModelGreeks {
+            delta,
+            gamma : pdf / (self.spot * self.volatility * sqrt_t),
+            theta : theta / 365.0,
+            vega : self.spot * pdf * sqrt_t / 100.0,
+            rho : rho / 100.0
+        }
+    }
+
+    // Solves for the volatility that reproduces `price`. Newton steps are used while vega is
+    // meaningful with bisection as the fallback so deep in or out of the money options converge.
+    pub fn implied_volatility(&self, price: f64) -> Result<f64> {
+        if self.time <= 0.0 {
+            return Err(eyre!("Can not imply volatility of an expired option"))
+        }
+
+        let bounded = |volatility: f64| OptionParams { volatility, ..*self }.price();
+        let (mut low, mut high) = (IV_MIN, IV_MAX);
+        if price < bounded(low) - IV_TOLERANCE || price > bounded(high) + IV_TOLERANCE {
+            return Err(eyre!("Price {} is outside the no-arbitrage bounds of the option", price))
+        }
+
+        let mut volatility = if self.volatility > 0.0 { self.volatility.clamp(low, high) } else { 0.5 };
+        for _ in 0..IV_MAX_ITERATIONS {
+            let params = OptionParams { volatility, ..*self };
+            let diff = params.price() - price;
+            if diff.abs() < IV_TOLERANCE {
+                return Ok(volatility)
+            }
+
+            if diff > 0.0 { high = volatility } else { low = volatility }
+
+            // Vega per unit of volatility
+            let vega = params.greeks().vega * 100.0;
+            let newton = volatility - diff / vega;
+            volatility = if vega > 1e-12 && newton > low && newton < high { newton } else { 0.5 * (low + high) };
+        }
+
+        Err(eyre!("Implied volatility did not converge for price {}", price))
+    }
+}
+
+// Model price and greeks for an option market from its forward, strike and quoted iv so they can
+// be compared against the greeks published by the exchange
+pub fn market_model(market: &MarketInfo, now: Timestamp) -> Option<(f64, ModelGreeks)> {
+    match market {
+        MarketInfo::Option { forward_price, strike, expiry, option_type, greeks, .. } => {
+            let params = OptionParams {
+                option_kind : if option_type == "put" { OptionKind::Put } else { OptionKind::Call },
+                spot : forward_price.to_f64()?,
+                strike : strike.to_f64()?,
+                time : year_fraction(*expiry, now),
+                volatility : greeks.iv.to_f64()?,
+                rate : 0.0
+            };
+            Some((params.price(), params.greeks()))
+        },
+        MarketInfo::Perp { .. } => None
+    }
+}
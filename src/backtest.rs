@@ -0,0 +1,410 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap}, fs, path::Path, time::Duration};
+use chrono::TimeDelta;
+use log::info;
+use eyre::{eyre, Result};
+use crate::{
+    batch::{CancelFilter, OrderRequest},
+    instruments::to_decimal,
+    paper::{PaperConfig, PaperExchange, PaperOrderRequest},
+    positions::PositionTracker,
+    recorder::{read_ndjson, RecordedMessage},
+    types::{Decimal, Timestamp},
+    ws_structs::{Fill, WsResponse, WsResponseData}
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestConfig {
+    // Fees and the account name of the simulated exchange
+    pub paper : PaperConfig,
+    // Time for an order op to reach the exchange, and again for its replies to come back
+    pub latency : Duration,
+    // Spacing of the points on the PnL curve, on top of the point added after every fill
+    pub sample_interval : Duration
+}
+
+impl Default for BacktestConfig {
+    fn default() -> BacktestConfig {
+        BacktestConfig {
+            paper : PaperConfig::default(),
+            latency : Duration::from_millis(50),
+            sample_interval : Duration::from_secs(60)
+        }
+    }
+}
+
+// A strategy sees the recorded public messages and the replies, orders, fills and positions of the simulated
+// exchange as the WsResponse values read_messages would deliver live
+pub trait Strategy {
+    fn on_message(&mut self, message: &WsResponse, ctx: &mut BacktestContext);
+}
+
+impl<F> Strategy for F
+where
+    F: FnMut(&WsResponse, &mut BacktestContext)
+{
+    fn on_message(&mut self, message: &WsResponse, ctx: &mut BacktestContext) {
+        self(message, ctx)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum OrderOp {
+    Create { request : PaperOrderRequest, id : Option<u64> },
+    Edit { order_id : String, request : PaperOrderRequest, id : Option<u64> },
+    Cancel { order_id : String },
+    CancelAll
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    // An order op reaches the exchange
+    Arrive(OrderOp),
+    // A message of the exchange reaches the strategy
+    Deliver(Box<WsResponse>)
+}
+
+// Order ops of a strategy. They reach the simulated exchange after the configured latency, results
+// come back as messages like they would on the websocket.
+#[derive(Debug)]
+pub struct BacktestContext {
+    now : Timestamp,
+    // Name and type of every instrument seen in the feeds
+    instruments : HashMap<u64, (String, String)>,
+    ops : Vec<OrderOp>,
+    orders : u64
+}
+
+impl BacktestContext {
+    pub fn now(&self) -> Timestamp {
+        self.now
+    }
+
+    fn request(&mut self, instrument_id: u64, is_buy: bool, limit_price: Option<f64>, quantity: f64, post_only: bool, ioc: bool) -> Result<PaperOrderRequest> {
+        let (instrument_name, instrument_type) = self.instruments.get(&instrument_id).cloned()
+            .ok_or_else(|| eyre!("Instrument {} not seen in the recorded feeds", instrument_id))?;
+        self.orders += 1;
+
+        Ok(PaperOrderRequest {
+            order_id : format!("backtest-{}", self.orders),
+            instrument_id,
+            asset : instrument_name.split('-').next().unwrap_or_default().to_string(),
+            instrument_name,
+            instrument_type,
+            is_buy,
+            price : limit_price.map(to_decimal).transpose()?,
+            amount : to_decimal(quantity)?,
+            post_only,
+            ioc
+        })
+    }
+
+    // Post-only unless the request says otherwise, like create_order
+    pub fn create_order(&mut self, order: &OrderRequest) -> Result<String> {
+        let request = self.request(order.instrument_id, order.is_buy, Some(order.limit_price), order.quantity, order.post_only.unwrap_or(true), false)?;
+        let order_id = request.order_id.clone();
+        self.ops.push(OrderOp::Create { request, id : order.id });
+        Ok(order_id)
+    }
+
+    pub fn create_market_order(&mut self, instrument_id: u64, is_buy: bool, quantity: f64) -> Result<String> {
+        let request = self.request(instrument_id, is_buy, None, quantity, false, true)?;
+        let order_id = request.order_id.clone();
+        self.ops.push(OrderOp::Create { request, id : None });
+        Ok(order_id)
+    }
+
+    pub fn edit_order(&mut self, order_id: &str, order: &OrderRequest) -> Result<String> {
+        let request = self.request(order.instrument_id, order.is_buy, Some(order.limit_price), order.quantity, order.post_only.unwrap_or(true), false)?;
+        let new_order_id = request.order_id.clone();
+        self.ops.push(OrderOp::Edit { order_id : order_id.to_string(), request, id : order.id });
+        Ok(new_order_id)
+    }
+
+    pub fn cancel_order(&mut self, order_id: &str) {
+        self.ops.push(OrderOp::Cancel { order_id : order_id.to_string() });
+    }
+
+    pub fn cancel_all_orders(&mut self) {
+        self.ops.push(OrderOp::CancelAll);
+    }
+
+    fn learn_instruments(&mut self, message: &WsResponse) {
+        let data = match message {
+            WsResponse::SubscribeResponse { data, .. } => data,
+            _ => return
+        };
+        match data {
+            WsResponseData::OrderBookData { instrument_id, instrument_name, instrument_type, .. }
+            | WsResponseData::TradesData { instrument_id, instrument_name, instrument_type, .. } => {
+                self.instruments.insert(*instrument_id, (instrument_name.clone(), instrument_type.clone()));
+            },
+            WsResponseData::BookTickerData { tickers, .. } => {
+                for ticker in tickers {
+                    self.instruments.insert(ticker.instrument_id, (ticker.instrument_name.clone(), ticker.instrument_type.clone()));
+                }
+            },
+            WsResponseData::TickerData { tickers, .. } => {
+                for ticker in tickers {
+                    self.instruments.insert(ticker.instrument_id, (ticker.instrument_name.clone(), ticker.instrument_type.clone()));
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnlPoint {
+    pub timestamp : Timestamp,
+    pub realized_pnl : Decimal,
+    pub unrealized_pnl : Decimal,
+    pub fees : Decimal,
+    // Realized plus unrealized PnL net of fees
+    pub equity : Decimal
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BacktestSummary {
+    pub start : Option<Timestamp>,
+    pub end : Option<Timestamp>,
+    pub fills : usize,
+    pub maker_fills : usize,
+    pub volume : Decimal,
+    pub notional : Decimal,
+    pub fees : Decimal,
+    pub realized_pnl : Decimal,
+    pub unrealized_pnl : Decimal,
+    pub net_pnl : Decimal,
+    // Largest fall of equity from a previous high on the PnL curve
+    pub max_drawdown : Decimal
+}
+
+#[derive(Debug, Clone)]
+pub struct BacktestResult {
+    pub trades : Vec<Fill>,
+    pub pnl_curve : Vec<PnlPoint>,
+    pub summary : BacktestSummary
+}
+
+// Account channels of the recording account. They describe its orders and not the simulated ones,
+// so they are left out of a replay.
+pub const PRIVATE_CHANNELS: [&str; 3] = ["fills", "orders", "positions"];
+
+pub fn is_private_channel(channel: &str) -> bool {
+    PRIVATE_CHANNELS.contains(&channel.split(':').next().unwrap_or(channel))
+}
+
+// Time a recorded message is replayed at: the exchange timestamp when there is one
+pub fn replay_time(record: &RecordedMessage) -> Timestamp {
+    record.exchange_ts.unwrap_or(record.received)
+}
+
+// Reads every NDJSON recording in the directory
+pub fn load_recordings(directory: impl AsRef<Path>) -> Result<Vec<RecordedMessage>> {
+    let mut paths: Vec<_> = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.to_string_lossy().ends_with(".ndjson.gz"))
+        .collect();
+    paths.sort();
+
+    let mut records = Vec::new();
+    for path in paths {
+        records.extend(read_ndjson(&path)?);
+    }
+    Ok(records)
+}
+
+// Replays recorded feeds through a strategy against the paper exchange's matching
+pub struct Backtester {
+    latency : TimeDelta,
+    sample_interval : TimeDelta,
+    exchange : PaperExchange,
+    positions : PositionTracker,
+    context : BacktestContext,
+    queue : BinaryHeap<Reverse<(Timestamp, u64)>>,
+    events : HashMap<u64, Event>,
+    sequence : u64,
+    trades : Vec<Fill>,
+    pnl_curve : Vec<PnlPoint>,
+    next_sample : Option<Timestamp>
+}
+
+impl Backtester {
+    pub fn new(config: BacktestConfig) -> Result<Backtester> {
+        Ok(Backtester {
+            latency : TimeDelta::from_std(config.latency)?,
+            sample_interval : TimeDelta::from_std(config.sample_interval)?,
+            exchange : PaperExchange::new(config.paper),
+            positions : PositionTracker::new(),
+            context : BacktestContext {
+                now : Timestamp::default(),
+                instruments : HashMap::new(),
+                ops : Vec::new(),
+                orders : 0
+            },
+            queue : BinaryHeap::new(),
+            events : HashMap::new(),
+            sequence : 0,
+            trades : Vec::new(),
+            pnl_curve : Vec::new(),
+            next_sample : None
+        })
+    }
+
+    // Replays the records in timestamp order, skipping the private channels. Order ops and replies
+    // still in flight when the records run out are dropped.
+    pub fn run<S: Strategy>(mut self, mut records: Vec<RecordedMessage>, strategy: &mut S) -> BacktestResult {
+        let recorded = records.len();
+        records.retain(|record| !is_private_channel(&record.channel));
+        if records.len() < recorded {
+            info!("Skipping {} recorded messages on private channels", recorded - records.len());
+        }
+        records.sort_by_key(replay_time);
+        let start = records.first().map(replay_time);
+        let end = records.last().map(replay_time);
+        info!("Backtesting {} messages from {:?} to {:?}", records.len(), start, end);
+
+        for record in records {
+            let now = replay_time(&record);
+            self.process_until(now, strategy);
+            self.set_time(now);
+
+            self.context.learn_instruments(&record.message);
+            let messages = self.exchange.apply_market(&record.message);
+            self.schedule_deliveries(now, messages);
+            self.update_marks();
+            self.deliver(&record.message, strategy);
+
+            if self.next_sample.is_none_or(|at| now >= at) {
+                self.sample(now);
+                self.next_sample = Some(now + self.sample_interval);
+            }
+        }
+        if let Some(end) = end {
+            self.sample(end);
+        }
+
+        self.result(start, end)
+    }
+
+    fn set_time(&mut self, now: Timestamp) {
+        self.context.now = now;
+        self.exchange.set_clock(now);
+    }
+
+    fn schedule(&mut self, at: Timestamp, event: Event) {
+        self.sequence += 1;
+        self.queue.push(Reverse((at, self.sequence)));
+        self.events.insert(self.sequence, event);
+    }
+
+    fn schedule_deliveries(&mut self, now: Timestamp, messages: Vec<WsResponse>) {
+        for message in messages {
+            self.schedule(now + self.latency, Event::Deliver(Box::new(message)));
+        }
+    }
+
+    fn process_until<S: Strategy>(&mut self, until: Timestamp, strategy: &mut S) {
+        while let Some(Reverse((at, sequence))) = self.queue.peek().copied() {
+            if at > until {
+                break
+            }
+            self.queue.pop();
+            self.set_time(at);
+            match self.events.remove(&sequence) {
+                Some(Event::Arrive(op)) => {
+                    let messages = self.execute(op);
+                    self.schedule_deliveries(at, messages);
+                },
+                Some(Event::Deliver(message)) => {
+                    self.positions.apply(&message);
+                    if let WsResponse::SubscribeResponse { data : WsResponseData::FillsData { fill, .. }, .. } = message.as_ref() {
+                        self.trades.push(fill.clone());
+                        self.update_marks();
+                        self.sample(at);
+                    }
+                    self.deliver(&message, strategy);
+                },
+                None => {}
+            }
+        }
+    }
+
+    fn execute(&mut self, op: OrderOp) -> Vec<WsResponse> {
+        let result = match op {
+            OrderOp::Create { request, id } => {
+                let order_id = request.order_id.clone();
+                self.exchange.submit(request).map(|messages| (self.exchange.order_reply(&order_id, id), messages)).map_err(|e| (id, e))
+            },
+            OrderOp::Edit { order_id, request, id } => {
+                let new_order_id = request.order_id.clone();
+                self.exchange.edit(&order_id, request).map(|messages| (self.exchange.order_reply(&new_order_id, id), messages)).map_err(|e| (id, e))
+            },
+            OrderOp::Cancel { order_id } => {
+                let reply = WsResponse::PublishResponse { id : None, data : WsResponseData::CancelOrderData { success : true, order_id : order_id.clone() } };
+                self.exchange.cancel(&order_id).map(|messages| (Some(reply), messages)).map_err(|e| (None, e))
+            },
+            OrderOp::CancelAll => {
+                let (order_ids, messages) = self.exchange.cancel_all(&CancelFilter::default());
+                let reply = WsResponse::PublishResponse { id : None, data : WsResponseData::CancelAllOrdersData { success : true, order_ids } };
+                Ok((Some(reply), messages))
+            }
+        };
+
+        match result {
+            Ok((reply, messages)) => reply.into_iter().chain(messages).collect(),
+            Err((id, e)) => vec![WsResponse::ErrorResponse { id, error : e.to_string() }]
+        }
+    }
+
+    fn deliver<S: Strategy>(&mut self, message: &WsResponse, strategy: &mut S) {
+        strategy.on_message(message, &mut self.context);
+        let now = self.context.now;
+        for op in std::mem::take(&mut self.context.ops) {
+            self.schedule(now + self.latency, Event::Arrive(op));
+        }
+    }
+
+    fn update_marks(&mut self) {
+        let open: Vec<u64> = self.positions.open_positions().iter().map(|p| p.instrument_id).collect();
+        for instrument_id in open {
+            if let Some(mid) = self.exchange.mid(instrument_id) {
+                self.positions.update_mark(instrument_id, mid);
+            }
+        }
+    }
+
+    fn sample(&mut self, now: Timestamp) {
+        let realized_pnl = self.positions.realized_pnl();
+        let unrealized_pnl = self.positions.unrealized_pnl();
+        let fees: Decimal = self.positions.positions().map(|p| p.fees).sum();
+        self.pnl_curve.push(PnlPoint { timestamp : now, realized_pnl, unrealized_pnl, fees, equity : realized_pnl + unrealized_pnl - fees });
+    }
+
+    fn result(self, start: Option<Timestamp>, end: Option<Timestamp>) -> BacktestResult {
+        let mut summary = BacktestSummary { start, end, ..Default::default() };
+        for fill in self.trades.iter() {
+            summary.fills += 1;
+            if fill.liquidity == "maker" {
+                summary.maker_fills += 1;
+            }
+            summary.volume += fill.filled;
+            summary.notional += fill.filled * fill.price;
+            summary.fees += fill.fees;
+        }
+
+        if let Some(last) = self.pnl_curve.last() {
+            summary.realized_pnl = last.realized_pnl;
+            summary.unrealized_pnl = last.unrealized_pnl;
+            summary.net_pnl = last.equity;
+        }
+        let mut peak: Option<Decimal> = None;
+        for point in self.pnl_curve.iter() {
+            let high = *peak.get_or_insert(point.equity);
+            peak = Some(high.max(point.equity));
+            summary.max_drawdown = summary.max_drawdown.max(high - point.equity);
+        }
+
+        BacktestResult { trades : self.trades, pnl_curve : self.pnl_curve, summary }
+    }
+}
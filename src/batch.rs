@@ -0,0 +1,134 @@
+use futures::future::join_all;
+use log::info;
+use eyre::{eyre, Result};
+use crate::{
+    aevo::AevoClient,
+    order_manager::TrackedOrder,
+    replies::REPLY_TIMEOUT,
+    rest::RestResponse,
+    ws_structs::{WsRequestData, WsResponse, WsResponseData}
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRequest {
+    pub instrument_id : u64,
+    pub is_buy : bool,
+    pub limit_price : f64,
+    pub quantity : f64,
+    pub post_only : Option<bool>,
+    pub mmp : Option<bool>,
+    // Websocket request id, set it to match error replies to the order
+    pub id : Option<u64>
+}
+
+impl OrderRequest {
+    pub fn new(instrument_id: u64, is_buy: bool, limit_price: f64, quantity: f64) -> OrderRequest {
+        OrderRequest { instrument_id, is_buy, limit_price, quantity, post_only : None, mmp : None, id : None }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditRequest {
+    pub order_id : String,
+    pub order : OrderRequest
+}
+
+// Filters of the mass cancel, left as None to match every order
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CancelFilter {
+    // OPTION or PERPETUAL
+    pub instrument_type : Option<String>,
+    pub asset : Option<String>,
+    pub instrument : Option<u64>
+}
+
+// Every batch call returns one result per input, in input order. Orders are prepared, risk checked
+// and signed concurrently and written to the websocket as soon as they are signed without waiting
+// for replies, which arrive through read_messages as usual. Each order reserves its exposure when it
+// passes the risk checks, so the batch as a whole stays within the limits.
+impl AevoClient {
+    pub async fn create_orders(&self, orders: &[OrderRequest]) -> Vec<Result<String>> {
+        info!("Creating batch of {} orders", orders.len());
+        join_all(orders.iter().map(|order| self.create_order(
+            order.instrument_id,
+            order.is_buy,
+            order.limit_price,
+            order.quantity,
+            order.post_only,
+            order.id,
+            order.mmp
+        ))).await
+    }
+
+    pub async fn edit_orders(&self, edits: &[EditRequest]) -> Vec<Result<String>> {
+        info!("Editing batch of {} orders", edits.len());
+        join_all(edits.iter().map(|edit| self.edit_order(
+            edit.order_id.clone(),
+            edit.order.instrument_id,
+            edit.order.is_buy,
+            edit.order.limit_price,
+            edit.order.quantity,
+            edit.order.id,
+            edit.order.post_only,
+            edit.order.mmp
+        ))).await
+    }
+
+    pub async fn cancel_orders(&self, order_ids: &[String]) -> Vec<Result<()>> {
+        info!("Cancelling batch of {} orders", order_ids.len());
+        join_all(order_ids.iter().map(|order_id| self.cancel_order(order_id.clone()))).await
+    }
+
+    // The REST api has no batch endpoint, the requests are sent concurrently instead
+    pub async fn rest_create_orders(&self, orders: &[OrderRequest], time_in_force: Option<String>) -> Vec<Result<RestResponse>> {
+        info!("Creating batch of {} rest orders", orders.len());
+        join_all(orders.iter().map(|order| self.rest_create_order(
+            order.instrument_id,
+            order.is_buy,
+            order.limit_price,
+            order.quantity,
+            order.post_only,
+            time_in_force.clone()
+        ))).await
+    }
+
+    pub async fn rest_cancel_orders(&self, order_ids: &[String]) -> Vec<Result<RestResponse>> {
+        info!("Cancelling batch of {} rest orders", order_ids.len());
+        join_all(order_ids.iter().map(|order_id| self.rest_cancel_order(order_id.clone()))).await
+    }
+
+    // Mass cancel over the websocket. Waits for the reply and returns the ids of the cancelled
+    // orders, so read_messages has to be running.
+    pub async fn cancel_all_orders_filtered(&self, filter: CancelFilter) -> Result<Vec<String>> {
+        info!("Cancelling all orders matching {:?}", filter);
+        if self.paper.is_some() {
+            return self.paper_cancel_all_orders(&filter).await
+        }
+
+        let data = WsRequestData::CancelAllOrdersData {
+            instrument_type : filter.instrument_type,
+            asset : filter.asset,
+            instrument : filter.instrument.map(|id| id.to_string())
+        };
+
+        match self.request("cancel_all_orders", data, REPLY_TIMEOUT).await? {
+            WsResponse::PublishResponse { data : WsResponseData::CancelAllOrdersData { success : true, order_ids }, .. } => Ok(order_ids),
+            response => Err(eyre!("Unexpected cancel_all_orders reply: {:?}", response))
+        }
+    }
+
+    // Cancels the locally tracked open orders matching the predicate. Returns the id of every order
+    // cancelled with its result.
+    pub async fn cancel_orders_where<F>(&self, predicate: F) -> Vec<(String, Result<()>)>
+    where
+        F: Fn(&TrackedOrder) -> bool
+    {
+        let order_ids: Vec<String> = self.orders.lock().await.open_orders().into_iter()
+            .filter(|order| predicate(order))
+            .map(|order| order.order_id.clone())
+            .collect();
+
+        let results = self.cancel_orders(&order_ids).await;
+        order_ids.into_iter().zip(results).collect()
+    }
+}
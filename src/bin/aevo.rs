@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use eyre::{eyre, Result};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use aevo_rust_sdk::{
+    aevo::{AevoClient, ClientCredentials},
+    env::ENV,
+    rest::RestResponse
+};
+
+#[derive(Parser, Debug)]
+#[command(name = "aevo", about = "Aevo account and trading operations")]
+struct Cli {
+    /// Use the testnet instead of mainnet
+    #[arg(long, global = true)]
+    testnet : bool,
+
+    /// TOML or dotenv file with signing_key, wallet_address, api_key, api_secret and optionally
+    /// wallet_private_key, or keystores in place of the keys. Credentials in the environment
+    /// take precedence.
+    #[arg(long, global = true, env = "AEVO_CONFIG")]
+    config : Option<PathBuf>,
+
+    #[command(subcommand)]
+    command : Command
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Markets of an asset
+    Markets {
+        #[arg(long, default_value = "ETH")]
+        asset : String
+    },
+    /// Index price of an asset
+    Index {
+        #[arg(long, default_value = "ETH")]
+        asset : String
+    },
+    /// Balances, positions and keys of the account
+    Account,
+    /// Balance and PnL summary
+    Portfolio,
+    /// Open orders
+    Orders,
+    /// Places a limit order, or a market order without --price
+    Place(OrderArgs),
+    /// Replaces an open order
+    Edit {
+        order_id : String,
+        #[command(flatten)]
+        order : OrderArgs
+    },
+    /// Cancels an order
+    Cancel {
+        order_id : String
+    },
+    /// Cancels every open order, optionally filtered
+    CancelAll {
+        /// OPTION or PERPETUAL
+        #[arg(long)]
+        instrument_type : Option<String>,
+        #[arg(long)]
+        asset : Option<String>
+    },
+    /// Withdraws collateral to L1
+    Withdraw {
+        amount : f64,
+        #[arg(long)]
+        collateral : Option<String>,
+        #[arg(long)]
+        to : Option<String>
+    },
+    /// Subscribes to websocket channels and prints every message as a line of JSON
+    Stream {
+        #[arg(required = true)]
+        channels : Vec<String>
+    }
+}
+
+#[derive(Args, Debug)]
+struct OrderArgs {
+    /// Instrument id or name, e.g. ETH-PERP
+    #[arg(long)]
+    instrument : String,
+    #[arg(long, value_enum)]
+    side : Side,
+    #[arg(long)]
+    amount : f64,
+    /// Limit price, a market order is sent without it
+    #[arg(long)]
+    price : Option<f64>,
+    #[arg(long)]
+    post_only : bool,
+    /// GTC or IOC
+    #[arg(long)]
+    time_in_force : Option<String>
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Side {
+    Buy,
+    Sell
+}
+
+async fn resolve_instrument(client: &AevoClient, instrument: &str) -> Result<u64> {
+    if let Ok(instrument_id) = instrument.parse::<u64>() {
+        return Ok(instrument_id)
+    }
+    let asset = instrument.split('-').next().unwrap_or(instrument).to_string();
+    client.load_instruments(asset).await?;
+    client.resolve_instrument_id(instrument).await.ok_or_else(|| eyre!("Unknown instrument {}", instrument))
+}
+
+fn print<T: Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+async fn place(client: &AevoClient, edit: Option<&String>, order: &OrderArgs) -> Result<RestResponse> {
+    let instrument_id = resolve_instrument(client, &order.instrument).await?;
+    let is_buy = order.side == Side::Buy;
+    match (edit, order.price) {
+        (Some(order_id), Some(price)) => {
+            client.rest_edit_order(order_id, instrument_id, is_buy, price, order.amount, Some(order.post_only), order.time_in_force.clone()).await
+        },
+        (Some(_), None) => Err(eyre!("Edits need a --price")),
+        (None, Some(price)) => {
+            client.rest_create_order(instrument_id, is_buy, price, order.amount, Some(order.post_only), order.time_in_force.clone()).await
+        },
+        (None, None) => client.rest_create_market_order(instrument_id, is_buy, order.amount).await
+    }
+}
+
+async fn stream(client: &AevoClient, channels: &[String]) -> Result<()> {
+    for channel in channels {
+        client.subscribe_ticker(channel.clone()).await?;
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let reader = client.read_raw_messages(tx);
+    let printer = async {
+        while let Some(message) = rx.recv().await {
+            println!("{}", message.raw);
+        }
+    };
+
+    tokio::select! {
+        result = reader => result,
+        _ = printer => Ok(()),
+        _ = tokio::signal::ctrl_c() => Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+
+    let credentials = ClientCredentials::load(cli.config.as_deref())?;
+    let env = if cli.testnet { ENV::TESTNET } else { ENV::MAINNET };
+    let client = AevoClient::new(credentials, env).await?;
+
+    match &cli.command {
+        Command::Markets { asset } => print(&client.get_markets(asset.clone()).await?)?,
+        Command::Index { asset } => print(&client.get_index(asset.clone()).await?)?,
+        Command::Account => print(&client.rest_get_account().await?)?,
+        Command::Portfolio => print(&client.rest_get_portfolio().await?)?,
+        Command::Orders => print(&client.rest_get_open_orders().await?)?,
+        Command::Place(order) => print(&place(&client, None, order).await?)?,
+        Command::Edit { order_id, order } => print(&place(&client, Some(order_id), order).await?)?,
+        Command::Cancel { order_id } => print(&client.rest_cancel_order(order_id.clone()).await?)?,
+        Command::CancelAll { instrument_type, asset } => {
+            print(&client.rest_cancel_all_orders(instrument_type.clone(), asset.clone()).await?)?
+        },
+        Command::Withdraw { amount, collateral, to } => {
+            print(&client.withdraw(*amount, collateral.clone(), to.clone(), None).await?)?
+        },
+        Command::Stream { channels } => stream(&client, channels).await?
+    }
+
+    client.close_connection().await
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Write},
+    fs,
+    path::Path
+};
+use alloy::signers::local::PrivateKeySigner;
+use eyre::{eyre, Result};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+use crate::aevo::ClientCredentials;
+
+// Settings read from the environment or a credentials file. Keys can be given in a keystore file
+// instead, decrypted with the password next to it.
+pub const SIGNING_KEY: &str = "SIGNING_KEY";
+pub const SIGNING_KEYSTORE: &str = "SIGNING_KEYSTORE";
+pub const SIGNING_KEYSTORE_PASSWORD: &str = "SIGNING_KEYSTORE_PASSWORD";
+pub const WALLET_ADDRESS: &str = "WALLET_ADDRESS";
+pub const WALLET_PRIVATE_KEY: &str = "WALLET_PRIVATE_KEY";
+pub const WALLET_KEYSTORE: &str = "WALLET_KEYSTORE";
+pub const WALLET_KEYSTORE_PASSWORD: &str = "WALLET_KEYSTORE_PASSWORD";
+pub const API_KEY: &str = "API_KEY";
+pub const API_SECRET: &str = "API_SECRET";
+
+const SETTINGS: [&str; 9] = [
+    SIGNING_KEY, SIGNING_KEYSTORE, SIGNING_KEYSTORE_PASSWORD, WALLET_ADDRESS, WALLET_PRIVATE_KEY,
+    WALLET_KEYSTORE, WALLET_KEYSTORE_PASSWORD, API_KEY, API_SECRET
+];
+
+const REDACTED: &str = "<redacted>";
+
+// A key or secret, zeroed on drop and redacted from Debug and Display. It still serializes to the
+// plain value since it has to go into requests, so log the request data rather than the message.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(Zeroizing<String>);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Secret {
+        Secret(Zeroizing::new(value.into()))
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Secret {
+        Secret::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Secret {
+        Secret::new(value)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose())
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Secret, D::Error> {
+        String::deserialize(deserializer).map(Secret::from)
+    }
+}
+
+#[derive(Default)]
+struct Settings {
+    values : HashMap<&'static str, Secret>
+}
+
+impl Settings {
+    fn from_env() -> Settings {
+        let values = SETTINGS.iter()
+            .filter_map(|name| std::env::var(name).ok().map(|value| (*name, Secret::from(value))))
+            .collect();
+        Settings { values }
+    }
+
+    // TOML for a .toml extension, dotenv otherwise. Keys are matched case insensitively and unknown
+    // keys are ignored.
+    fn from_file(path: &Path) -> Result<Settings> {
+        let pairs: Vec<(String, Secret)> = if path.extension().is_some_and(|e| e == "toml") {
+            let text = Zeroizing::new(fs::read_to_string(path)?);
+            let table: toml::Table = toml::from_str(&text).map_err(|e| eyre!("Problem reading {}: {}", path.display(), e))?;
+            table.into_iter()
+                .map(|(key, value)| match value {
+                    toml::Value::String(value) => Ok((key, Secret::from(value))),
+                    _ => Err(eyre!("Problem reading {}: {} is not a string", path.display(), key))
+                })
+                .collect::<Result<_>>()?
+        } else {
+            dotenvy::from_path_iter(path)
+                .map_err(|e| eyre!("Problem reading {}: {}", path.display(), e))?
+                .map(|item| item.map(|(key, value)| (key, Secret::from(value))).map_err(|e| eyre!("Problem reading {}: {}", path.display(), e)))
+                .collect::<Result<_>>()?
+        };
+
+        let mut values = HashMap::new();
+        for (key, value) in pairs {
+            if let Some(name) = SETTINGS.iter().find(|name| name.eq_ignore_ascii_case(&key)) {
+                values.insert(*name, value);
+            }
+        }
+        Ok(Settings { values })
+    }
+
+    // Keeps our values and takes the missing ones from other
+    fn or(mut self, other: Settings) -> Settings {
+        for (name, value) in other.values {
+            self.values.entry(name).or_insert(value);
+        }
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&Secret> {
+        self.values.get(name).filter(|value| !value.is_empty())
+    }
+
+    // A key given directly or through a keystore, but not both
+    fn key(&self, name: &str, keystore: &str, password: &str) -> Result<Option<Secret>> {
+        match (self.get(name), self.get(keystore)) {
+            (Some(_), Some(_)) => Err(eyre!("Both {} and {} are set", name, keystore)),
+            (Some(key), None) => Ok(Some(key.clone())),
+            (None, Some(path)) => {
+                let password = self.get(password).ok_or_else(|| eyre!("{} is set without {}", keystore, password))?;
+                Ok(Some(decrypt_keystore(Path::new(path.expose()), password.expose())?))
+            },
+            (None, None) => Ok(None)
+        }
+    }
+
+    fn credentials(&self) -> Result<Option<ClientCredentials>> {
+        let signing_key = self.key(SIGNING_KEY, SIGNING_KEYSTORE, SIGNING_KEYSTORE_PASSWORD)?;
+        let wallet_private_key = self.key(WALLET_PRIVATE_KEY, WALLET_KEYSTORE, WALLET_KEYSTORE_PASSWORD)?;
+
+        // The wallet address follows from the wallet key when only that is given
+        let wallet_address = match (self.get(WALLET_ADDRESS), &wallet_private_key) {
+            (Some(address), _) => Some(address.expose().to_string()),
+            (None, Some(key)) => {
+                let signer: PrivateKeySigner = key.expose().parse().map_err(|_| eyre!("Invalid {}", WALLET_PRIVATE_KEY))?;
+                Some(signer.address().to_string())
+            },
+            (None, None) => None
+        };
+        let api_key = self.get(API_KEY).map(|key| key.expose().to_string());
+        let api_secret = self.get(API_SECRET).cloned();
+
+        match (signing_key, wallet_address, api_key, api_secret) {
+            (Some(signing_key), Some(wallet_address), Some(api_key), Some(api_secret)) => Ok(Some(ClientCredentials {
+                signing_key,
+                wallet_address,
+                wallet_private_key,
+                api_key,
+                api_secret
+            })),
+            (None, None, None, None) => Ok(None),
+            _ => Err(eyre!("Incomplete credentials: {}, {}, {} and {} are all needed", SIGNING_KEY, WALLET_ADDRESS, API_KEY, API_SECRET))
+        }
+    }
+}
+
+// Decrypts an Ethereum JSON keystore (scrypt or pbkdf2) into a 0x prefixed hex private key
+pub fn decrypt_keystore(path: &Path, password: &str) -> Result<Secret> {
+    let key = Zeroizing::new(
+        eth_keystore::decrypt_key(path, password).map_err(|e| eyre!("Problem decrypting {}: {}", path.display(), e))?
+    );
+    let mut hex = Zeroizing::new(String::with_capacity(2 + 2 * key.len()));
+    hex.push_str("0x");
+    for byte in key.iter() {
+        write!(hex, "{:02x}", byte)?;
+    }
+    Ok(Secret(hex))
+}
+
+impl ClientCredentials {
+    // From SIGNING_KEY, WALLET_ADDRESS, API_KEY, API_SECRET and optionally WALLET_PRIVATE_KEY, or the
+    // keystore settings in their place
+    pub fn from_env() -> Result<ClientCredentials> {
+        Settings::from_env().credentials()?.ok_or_else(|| eyre!("No credentials in the environment"))
+    }
+
+    // From a TOML file when it has a .toml extension and a dotenv file otherwise, with the same keys
+    // as from_env in upper or lower case
+    pub fn from_file(path: impl AsRef<Path>) -> Result<ClientCredentials> {
+        let path = path.as_ref();
+        Settings::from_file(path)?.credentials()?.ok_or_else(|| eyre!("No credentials in {}", path.display()))
+    }
+
+    // The environment, falling back on the file for settings it does not have. None when neither has
+    // any credentials, for clients that only use public endpoints.
+    pub fn load(path: Option<&Path>) -> Result<Option<ClientCredentials>> {
+        let settings = match path {
+            Some(path) => Settings::from_env().or(Settings::from_file(path)?),
+            None => Settings::from_env()
+        };
+        settings.credentials()
+    }
+
+    // AEVO-KEY and AEVO-SECRET for REST requests, marked sensitive so they are left out of the
+    // request's Debug output
+    pub fn auth_headers(&self) -> Result<HeaderMap> {
+        let mut key = HeaderValue::from_str(&self.api_key)?;
+        let mut secret = HeaderValue::from_str(self.api_secret.expose())?;
+        key.set_sensitive(true);
+        secret.set_sensitive(true);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("AEVO-KEY", key);
+        headers.insert("AEVO-SECRET", secret);
+        Ok(headers)
+    }
+}
@@ -0,0 +1,315 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use rust_decimal::prelude::ToPrimitive;
+use tokio::{sync::{broadcast::error::RecvError, mpsc::{self, UnboundedReceiver, UnboundedSender}}, task::JoinHandle};
+use crate::{
+    aevo::AevoClient,
+    order_manager::OrderEvent,
+    rest::RestResponse,
+    types::Decimal,
+    ws_structs::{WsResponse, WsResponseData}
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlgoKind {
+    // Even slices over the duration
+    Twap { duration : Duration },
+    // Trades `participation` of the volume printed on the trades channel since the last slice,
+    // stopping at the end of the duration
+    Vwap { duration : Duration, participation : Decimal }
+}
+
+impl AlgoKind {
+    pub fn duration(&self) -> Duration {
+        match self {
+            AlgoKind::Twap { duration } | AlgoKind::Vwap { duration, .. } => *duration
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlgoParams {
+    pub instrument_id : u64,
+    pub is_buy : bool,
+    pub quantity : Decimal,
+    pub kind : AlgoKind,
+    // Time between child orders
+    pub interval : Duration,
+    // Child orders are IOC limits at this price, so buys never pay more and sells never receive
+    // less. Without it children are market orders.
+    pub limit_price : Option<Decimal>,
+    // Caps every child at this fraction of the volume traded since the previous child
+    pub max_participation : Option<Decimal>,
+    // Smallest child worth sending, usually the instrument's amount step
+    pub min_child : Decimal
+}
+
+impl AlgoParams {
+    // VWAP and participation caps size children from the volume on the trades channel
+    pub fn needs_trades(&self) -> bool {
+        matches!(self.kind, AlgoKind::Vwap { .. }) || self.max_participation.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlgoStatus {
+    Running,
+    Paused,
+    Cancelled,
+    Completed,
+    // The duration passed before the full quantity traded
+    Expired,
+    // A chase order ran into its slippage limit
+    LimitReached
+}
+
+impl AlgoStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, AlgoStatus::Running | AlgoStatus::Paused)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlgoProgress {
+    pub quantity : Decimal,
+    pub filled : Decimal,
+    pub avg_price : Option<Decimal>,
+    pub child_orders : Vec<String>,
+    pub status : AlgoStatus
+}
+
+impl AlgoProgress {
+    pub fn remaining(&self) -> Decimal {
+        (self.quantity - self.filled).max(Decimal::ZERO)
+    }
+}
+
+// Size of the next child order. `elapsed` is the time the algo has been running, not counting
+// pauses, and `interval_volume` is the market volume traded since the previous child.
+pub fn child_quantity(params: &AlgoParams, elapsed: Duration, filled: Decimal, interval_volume: Decimal) -> Decimal {
+    let remaining = (params.quantity - filled).max(Decimal::ZERO);
+
+    let mut child = match &params.kind {
+        AlgoKind::Twap { duration } => {
+            let progress = if duration.is_zero() { 1.0 } else { (elapsed.as_secs_f64() / duration.as_secs_f64()).min(1.0) };
+            let scheduled = params.quantity * Decimal::try_from(progress).unwrap_or(Decimal::ONE);
+            (scheduled - filled).max(Decimal::ZERO)
+        },
+        AlgoKind::Vwap { participation, .. } => interval_volume * participation
+    };
+
+    if let Some(cap) = params.max_participation {
+        child = child.min(interval_volume * cap);
+    }
+    child = child.min(remaining);
+
+    if !params.min_child.is_zero() {
+        child = (child / params.min_child).floor() * params.min_child;
+    }
+    if child < params.min_child || child.is_zero() {
+        return Decimal::ZERO
+    }
+    child
+}
+
+#[derive(Debug)]
+struct AlgoControl {
+    status : AlgoStatus,
+    child_orders : Vec<String>,
+    subscribers : Vec<UnboundedSender<OrderEvent>>
+}
+
+#[derive(Debug, Clone)]
+pub struct AlgoHandle {
+    client : Arc<AevoClient>,
+    quantity : Decimal,
+    control : Arc<Mutex<AlgoControl>>
+}
+
+// Shared by the execution algos, iceberg and chase orders to control them and follow their children
+impl AlgoHandle {
+    pub(crate) fn new(client: Arc<AevoClient>, quantity: Decimal) -> AlgoHandle {
+        let control = AlgoControl { status : AlgoStatus::Running, child_orders : Vec::new(), subscribers : Vec::new() };
+        AlgoHandle { client, quantity, control : Arc::new(Mutex::new(control)) }
+    }
+
+    pub(crate) fn push_child(&self, order_id: &str) {
+        self.control.lock().unwrap().child_orders.push(order_id.to_string());
+    }
+
+    pub(crate) fn is_child(&self, order_id: &str) -> bool {
+        self.control.lock().unwrap().child_orders.iter().any(|id| id == order_id)
+    }
+
+    // Events of the child orders, including their fills
+    pub fn subscribe(&self) -> UnboundedReceiver<OrderEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.control.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+
+    pub(crate) fn notify(&self, event: &OrderEvent) {
+        self.control.lock().unwrap().subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    pub(crate) fn set_status(&self, status: AlgoStatus) {
+        let mut control = self.control.lock().unwrap();
+        if !control.status.is_finished() {
+            control.status = status;
+        }
+    }
+
+    pub fn pause(&self) {
+        self.set_status(AlgoStatus::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.set_status(AlgoStatus::Running);
+    }
+
+    // Stops sending children. Children are IOC so nothing is left resting.
+    pub fn cancel(&self) {
+        self.set_status(AlgoStatus::Cancelled);
+    }
+
+    pub fn status(&self) -> AlgoStatus {
+        self.control.lock().unwrap().status.clone()
+    }
+
+    // Fills are read from the order manager, which sees both the REST replies and the fills channel
+    pub async fn progress(&self) -> AlgoProgress {
+        let (status, child_orders) = {
+            let control = self.control.lock().unwrap();
+            (control.status.clone(), control.child_orders.clone())
+        };
+
+        let orders = self.client.orders.lock().await;
+        let mut filled = Decimal::ZERO;
+        let mut cost = Decimal::ZERO;
+        for order in child_orders.iter().filter_map(|id| orders.get(id)) {
+            filled += order.filled;
+            cost += order.filled * order.avg_price.or(order.price).unwrap_or_default();
+        }
+
+        AlgoProgress {
+            quantity : self.quantity,
+            filled,
+            avg_price : if filled.is_zero() { None } else { Some(cost / filled) },
+            child_orders,
+            status
+        }
+    }
+}
+
+impl AevoClient {
+    // Sends one IOC child and returns its order id
+    async fn send_child(&self, params: &AlgoParams, quantity: Decimal) -> Result<String> {
+        let quantity = quantity.to_f64().ok_or_else(|| eyre!("Child quantity {} out of range", quantity))?;
+        let response = match params.limit_price {
+            Some(price) => {
+                let price = price.to_f64().ok_or_else(|| eyre!("Limit price {} out of range", price))?;
+                self.rest_create_order(params.instrument_id, params.is_buy, price, quantity, Some(false), Some("IOC".to_string())).await?
+            },
+            None => self.rest_create_market_order(params.instrument_id, params.is_buy, quantity).await?
+        };
+
+        match response {
+            RestResponse::CreateOrder(order) => Ok(order.order_id),
+            response => Err(eyre!("Unexpected create order response: {:?}", response))
+        }
+    }
+
+    // Runs a TWAP or VWAP until the quantity is filled, the duration passes or it is cancelled.
+    // Time spent paused does not count towards the duration. VWAP and participation caps subscribe
+    // to the trades of the instrument, which has to be loaded, and need read_messages running.
+    // Without a trade feed they would never trade, so failing to subscribe is an error.
+    pub async fn spawn_algo(client: Arc<AevoClient>, params: AlgoParams) -> Result<(AlgoHandle, JoinHandle<()>)> {
+        if params.needs_trades() {
+            let instrument_name = client.resolve_instrument_name(params.instrument_id).await
+                .ok_or_else(|| eyre!("Instrument {} is not loaded, participation needs its trades", params.instrument_id))?;
+            client.subscribe_trades(instrument_name).await
+                .map_err(|e| eyre!("Participation needs the trades of instrument {}: {}", params.instrument_id, e))?;
+        }
+
+        let handle = AlgoHandle::new(client.clone(), params.quantity);
+        let algo = handle.clone();
+
+        let task = tokio::spawn(async move {
+            info!("Starting {:?} for {} on instrument {}", params.kind, params.quantity, params.instrument_id);
+            let mut events = client.events.subscribe();
+            let mut order_events = client.orders.lock().await.subscribe();
+            let mut interval = tokio::time::interval(params.interval);
+            let start = Instant::now();
+            let mut paused = Duration::ZERO;
+            let mut paused_since: Option<Instant> = None;
+            let mut interval_volume = Decimal::ZERO;
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {},
+                    event = events.recv() => {
+                        match event {
+                            Ok(WsResponse::SubscribeResponse { data : WsResponseData::TradesData { instrument_id, amount : Some(amount), .. }, .. })
+                                if instrument_id == params.instrument_id => interval_volume += amount,
+                            Err(RecvError::Lagged(skipped)) => warn!("Algo missed {} market events", skipped),
+                            _ => {}
+                        }
+                        continue;
+                    },
+                    Some(event) = order_events.recv() => {
+                        if algo.is_child(&event.order.order_id) {
+                            algo.notify(&event);
+                        }
+                        continue;
+                    }
+                }
+
+                match algo.status() {
+                    AlgoStatus::Running => {
+                        if let Some(since) = paused_since.take() {
+                            paused += since.elapsed();
+                        }
+                    },
+                    AlgoStatus::Paused => {
+                        paused_since.get_or_insert_with(Instant::now);
+                        interval_volume = Decimal::ZERO;
+                        continue;
+                    },
+                    _ => break
+                }
+
+                let progress = algo.progress().await;
+                if progress.remaining().is_zero() {
+                    algo.set_status(AlgoStatus::Completed);
+                    break
+                }
+
+                // Resuming carries on with the schedule rather than catching up on the pause
+                let elapsed = start.elapsed().saturating_sub(paused);
+                let child = child_quantity(&params, elapsed, progress.filled, interval_volume);
+                if !child.is_zero() {
+                    match client.send_child(&params, child).await {
+                        Ok(order_id) => {
+                            interval_volume = Decimal::ZERO;
+                            algo.push_child(&order_id);
+                        },
+                        Err(e) => error!("Problem sending child order: {}", e)
+                    }
+                }
+
+                // The slice sent once the duration has passed is the last one
+                if elapsed >= params.kind.duration() {
+                    let progress = algo.progress().await;
+                    algo.set_status(if progress.remaining().is_zero() { AlgoStatus::Completed } else { AlgoStatus::Expired });
+                    break
+                }
+            }
+
+            let progress = algo.progress().await;
+            info!("Algo on instrument {} finished {:?} with {} of {} filled", params.instrument_id, progress.status, progress.filled, progress.quantity);
+        });
+
+        Ok((handle, task))
+    }
+}
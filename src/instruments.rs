@@ -0,0 +1,378 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+use chrono::NaiveDate;
+use log::{info, debug, error};
+use eyre::{eyre, Result};
+use rust_decimal::prelude::*;
+use tokio::task::JoinHandle;
+use crate::{aevo::AevoClient, rest::{MarketInfo, RestResponse}, types::{Decimal, Timestamp}, ws_structs::Position};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instrument {
+    pub instrument_id : u64,
+    pub instrument_name : String,
+    pub instrument_type : String,
+    pub underlying_asset : String,
+    pub price_step : Decimal,
+    pub amount_step : Decimal,
+    pub min_order_value : Decimal,
+    pub max_order_value : Decimal,
+    pub max_leverage : Option<Decimal>,
+    pub expiry : Option<Timestamp>,
+    pub is_active : bool
+}
+
+impl From<MarketInfo> for Instrument {
+    fn from(market: MarketInfo) -> Self {
+        match market {
+            MarketInfo::Perp {
+                instrument_id, instrument_name, instrument_type, underlying_asset, price_step, amount_step,
+                min_order_value, max_order_value, is_active, max_leverage, ..
+            } => Instrument {
+                instrument_id, instrument_name, instrument_type, underlying_asset, price_step, amount_step,
+                min_order_value, max_order_value, is_active,
+                max_leverage : Some(max_leverage),
+                expiry : None
+            },
+            MarketInfo::Option {
+                instrument_id, instrument_name, instrument_type, underlying_asset, price_step, amount_step,
+                min_order_value, max_order_value, is_active, expiry, ..
+            } => Instrument {
+                instrument_id, instrument_name, instrument_type, underlying_asset, price_step, amount_step,
+                min_order_value, max_order_value, is_active,
+                max_leverage : None,
+                expiry : Some(expiry)
+            }
+        }
+    }
+}
+
+impl Instrument {
+    // Buys are rounded down and sells up so the rounded price is never more aggressive than requested
+    pub fn round_price(&self, price: Decimal, is_buy: bool) -> Decimal {
+        if self.price_step.is_zero() {
+            return price
+        }
+
+        let steps = price / self.price_step;
+        let steps = if is_buy { steps.floor() } else { steps.ceil() };
+        (steps * self.price_step).normalize()
+    }
+
+    pub fn round_amount(&self, amount: Decimal) -> Decimal {
+        if self.amount_step.is_zero() {
+            return amount
+        }
+
+        ((amount / self.amount_step).floor() * self.amount_step).normalize()
+    }
+
+    pub fn validate(&self, price: Option<Decimal>, amount: Decimal) -> Result<()> {
+        if !self.is_active {
+            return Err(eyre!("Instrument {} is not active", self.instrument_name))
+        }
+
+        if amount <= Decimal::ZERO {
+            return Err(eyre!("Order amount {} is below the amount step {} of {}", amount, self.amount_step, self.instrument_name))
+        }
+
+        if let Some(price) = price {
+            if price <= Decimal::ZERO {
+                return Err(eyre!("Order price {} is below the price step {} of {}", price, self.price_step, self.instrument_name))
+            }
+
+            let value = price * amount;
+            if value < self.min_order_value {
+                return Err(eyre!("Order value {} is below the minimum {} of {}", value, self.min_order_value, self.instrument_name))
+            }
+
+            if value > self.max_order_value {
+                return Err(eyre!("Order value {} is above the maximum {} of {}", value, self.max_order_value, self.instrument_name))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Instruments {
+    by_id : HashMap<u64, Instrument>,
+    by_name : HashMap<String, u64>,
+}
+
+impl Instruments {
+    pub fn new() -> Instruments {
+        Instruments::default()
+    }
+
+    pub fn update(&mut self, markets: Vec<MarketInfo>) {
+        for market in markets {
+            let instrument = Instrument::from(market);
+            self.by_name.insert(instrument.instrument_name.clone(), instrument.instrument_id);
+            self.by_id.insert(instrument.instrument_id, instrument);
+        }
+    }
+
+    // Replaces the instruments of an asset with a fresh markets response, dropping the ones it no
+    // longer lists such as expired options
+    pub fn replace_asset(&mut self, asset: &str, markets: Vec<MarketInfo>) {
+        let stale: Vec<u64> = self.by_id.values()
+            .filter(|i| i.underlying_asset.eq_ignore_ascii_case(asset))
+            .map(|i| i.instrument_id)
+            .collect();
+        for instrument_id in stale {
+            if let Some(instrument) = self.by_id.remove(&instrument_id) {
+                self.by_name.remove(&instrument.instrument_name);
+            }
+        }
+        self.update(markets);
+    }
+
+    pub fn get(&self, instrument_id: u64) -> Option<&Instrument> {
+        self.by_id.get(&instrument_id)
+    }
+
+    pub fn get_by_name(&self, instrument_name: &str) -> Option<&Instrument> {
+        self.by_name.get(instrument_name).and_then(|id| self.by_id.get(id))
+    }
+
+    pub fn instrument_id(&self, instrument_name: &str) -> Option<u64> {
+        self.by_name.get(instrument_name).copied()
+    }
+
+    pub fn instrument_name(&self, instrument_id: u64) -> Option<&str> {
+        self.by_id.get(&instrument_id).map(|i| i.instrument_name.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.by_id.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    // Rounds the price and amount onto the instrument grid and validates the result. Orders on
+    // instruments that are not loaded in the cache are rejected rather than sent unrounded.
+    pub fn prepare_order(
+        &self,
+        instrument_id: u64,
+        is_buy: bool,
+        limit_price: Option<f64>,
+        quantity: f64
+    ) -> Result<(Option<f64>, f64)> {
+        let instrument = match self.get(instrument_id) {
+            Some(i) => i,
+            None => return Err(eyre!("Instrument {} is not loaded, call load_instruments for its asset first", instrument_id))
+        };
+
+        let price = match limit_price {
+            Some(p) => Some(instrument.round_price(to_decimal(p)?, is_buy)),
+            None => None
+        };
+        let amount = instrument.round_amount(to_decimal(quantity)?);
+
+        instrument.validate(price, amount)?;
+
+        debug!("Order on {} rounded to price {:?} amount {}", instrument.instrument_name, price, amount);
+
+        Ok((price.and_then(|p| p.to_f64()), amount.to_f64().unwrap_or(quantity)))
+    }
+}
+
+pub fn to_decimal(value: f64) -> Result<Decimal> {
+    Decimal::from_f64(value).ok_or_else(|| eyre!("{} can not be represented as a decimal", value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionKind {
+    Call,
+    Put
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InstrumentKind {
+    Perpetual,
+    Option {
+        expiry : NaiveDate,
+        strike : Decimal,
+        option_kind : OptionKind
+    }
+}
+
+// Structured form of names like ETH-PERP or ETH-27SEP24-3000-C
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstrumentName {
+    pub underlying : String,
+    pub kind : InstrumentKind
+}
+
+impl InstrumentName {
+    pub fn perpetual(underlying: &str) -> InstrumentName {
+        InstrumentName { underlying : underlying.to_string(), kind : InstrumentKind::Perpetual }
+    }
+
+    pub fn option(underlying: &str, expiry: NaiveDate, strike: Decimal, option_kind: OptionKind) -> InstrumentName {
+        InstrumentName {
+            underlying : underlying.to_string(),
+            kind : InstrumentKind::Option { expiry, strike, option_kind }
+        }
+    }
+
+    pub fn is_perpetual(&self) -> bool {
+        self.kind == InstrumentKind::Perpetual
+    }
+
+    pub fn is_option(&self) -> bool {
+        !self.is_perpetual()
+    }
+
+    pub fn is_call(&self) -> bool {
+        self.option_kind() == Some(OptionKind::Call)
+    }
+
+    pub fn is_put(&self) -> bool {
+        self.option_kind() == Some(OptionKind::Put)
+    }
+
+    pub fn expiry(&self) -> Option<NaiveDate> {
+        match self.kind {
+            InstrumentKind::Option { expiry, .. } => Some(expiry),
+            InstrumentKind::Perpetual => None
+        }
+    }
+
+    pub fn strike(&self) -> Option<Decimal> {
+        match self.kind {
+            InstrumentKind::Option { strike, .. } => Some(strike),
+            InstrumentKind::Perpetual => None
+        }
+    }
+
+    pub fn option_kind(&self) -> Option<OptionKind> {
+        match self.kind {
+            InstrumentKind::Option { option_kind, .. } => Some(option_kind),
+            InstrumentKind::Perpetual => None
+        }
+    }
+}
+
+impl FromStr for InstrumentName {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split('-').collect();
+        match parts.as_slice() {
+            [underlying, "PERP"] if !underlying.is_empty() => Ok(InstrumentName::perpetual(underlying)),
+            [underlying, expiry, strike, option_kind] if !underlying.is_empty() => {
+                // Display writes the day without padding, so a padded day would not round trip
+                if expiry.starts_with('0') {
+                    return Err(eyre!("Invalid expiry in instrument name {}: day has a leading zero", s))
+                }
+                let expiry = NaiveDate::parse_from_str(expiry, "%d%b%y")
+                    .map_err(|e| eyre!("Invalid expiry in instrument name {}: {}", s, e))?;
+                let strike = Decimal::from_str(strike)
+                    .map_err(|e| eyre!("Invalid strike in instrument name {}: {}", s, e))?;
+                let option_kind = match *option_kind {
+                    "C" => OptionKind::Call,
+                    "P" => OptionKind::Put,
+                    other => return Err(eyre!("Invalid option type {} in instrument name {}", other, s))
+                };
+                Ok(InstrumentName::option(underlying, expiry, strike, option_kind))
+            },
+            _ => Err(eyre!("Unrecognised instrument name: {}", s))
+        }
+    }
+}
+
+impl fmt::Display for InstrumentName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            InstrumentKind::Perpetual => write!(f, "{}-PERP", self.underlying),
+            InstrumentKind::Option { expiry, strike, option_kind } => write!(
+                f,
+                "{}-{}-{}-{}",
+                self.underlying,
+                expiry.format("%-d%b%y").to_string().to_uppercase(),
+                strike.normalize(),
+                match option_kind {
+                    OptionKind::Call => "C",
+                    OptionKind::Put => "P"
+                }
+            )
+        }
+    }
+}
+
+impl MarketInfo {
+    pub fn instrument_id(&self) -> u64 {
+        match self {
+            MarketInfo::Perp { instrument_id, .. } | MarketInfo::Option { instrument_id, .. } => *instrument_id
+        }
+    }
+
+    pub fn instrument_name(&self) -> &str {
+        match self {
+            MarketInfo::Perp { instrument_name, .. } | MarketInfo::Option { instrument_name, .. } => instrument_name
+        }
+    }
+
+    pub fn parsed_name(&self) -> Result<InstrumentName> {
+        self.instrument_name().parse()
+    }
+}
+
+impl Position {
+    pub fn parsed_name(&self) -> Result<InstrumentName> {
+        self.instrument_name.parse()
+    }
+}
+
+impl AevoClient {
+    pub async fn load_instruments(&self, asset: String) -> Result<()> {
+        info!("Loading {} instruments", asset);
+        match self.get_markets(asset.clone()).await? {
+            RestResponse::GetMarkets(markets) => {
+                self.instruments.write().await.replace_asset(&asset, markets);
+                Ok(())
+            },
+            response => Err(eyre!("Unexpected markets response: {:?}", response))
+        }
+    }
+
+    pub async fn resolve_instrument_id(&self, instrument_name: &str) -> Option<u64> {
+        self.instruments.read().await.instrument_id(instrument_name)
+    }
+
+    pub async fn resolve_instrument_name(&self, instrument_id: u64) -> Option<String> {
+        self.instruments.read().await.instrument_name(instrument_id).map(|n| n.to_string())
+    }
+
+    pub async fn prepare_order(
+        &self,
+        instrument_id: u64,
+        is_buy: bool,
+        limit_price: Option<f64>,
+        quantity: f64
+    ) -> Result<(Option<f64>, f64)> {
+        self.instruments.read().await.prepare_order(instrument_id, is_buy, limit_price, quantity)
+    }
+
+    pub fn spawn_instruments_refresh(client: Arc<AevoClient>, assets: Vec<String>, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                for asset in assets.iter() {
+                    if let Err(e) = client.load_instruments(asset.clone()).await {
+                        error!("Problem refreshing {} instruments: {}", asset, e);
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+use alloy::signers::local::PrivateKeySigner;
+use chrono::{Duration, Utc};
+use eyre::{eyre, Result};
+use log::{info, warn, error};
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+use crate::{
+    aevo::{AevoClient, ClientCredentials},
+    credentials::Secret,
+    registration::{signer_key, DEFAULT_KEY_LIFETIME_DAYS},
+    rest::{RestResponse, SigningKeyInfo},
+    types::Timestamp
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyExpiryConfig {
+    pub check_interval : std::time::Duration,
+    // Warn once the signing key expires within this
+    pub warn_before : Duration,
+    // Register a new signing key once the current one expires within this, None only warns
+    pub rotate_before : Option<Duration>,
+    // Lifetime of keys registered by rotation
+    pub lifetime : Duration
+}
+
+impl Default for KeyExpiryConfig {
+    fn default() -> KeyExpiryConfig {
+        KeyExpiryConfig {
+            check_interval : std::time::Duration::from_secs(3600),
+            warn_before : Duration::days(7),
+            rotate_before : None,
+            lifetime : Duration::days(DEFAULT_KEY_LIFETIME_DAYS)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyStatus {
+    Valid(Timestamp),
+    // Expires within warn_before
+    Expiring(Timestamp),
+    Expired(Timestamp),
+    // Not among the account's signing keys, e.g. revoked
+    Unknown
+}
+
+impl KeyStatus {
+    pub fn expiry(&self) -> Option<Timestamp> {
+        match self {
+            KeyStatus::Valid(expiry) | KeyStatus::Expiring(expiry) | KeyStatus::Expired(expiry) => Some(*expiry),
+            KeyStatus::Unknown => None
+        }
+    }
+}
+
+// Status of the signing key with the given address among the account's keys
+pub fn key_status(keys: &[SigningKeyInfo], address: &str, now: Timestamp, warn_before: Duration) -> KeyStatus {
+    match keys.iter().find(|key| key.signing_key.eq_ignore_ascii_case(address)) {
+        Some(key) if key.expiry <= now => KeyStatus::Expired(key.expiry),
+        Some(key) if key.expiry - now <= warn_before => KeyStatus::Expiring(key.expiry),
+        Some(key) => KeyStatus::Valid(key.expiry),
+        None => KeyStatus::Unknown
+    }
+}
+
+impl AevoClient {
+    // The key orders and withdrawals are signed with, the rotated one once there is one
+    pub fn current_signing_key(&self) -> Result<Secret> {
+        let rotated_key = self.rotated_key.read().map_err(|_| eyre!("Rotated signing key lock poisoned"))?;
+        if let Some(key) = rotated_key.as_ref() {
+            return Ok(key.clone())
+        }
+        self.credentials
+            .as_ref()
+            .map(|credentials| credentials.signing_key.clone())
+            .ok_or_else(|| eyre!("Order sign error: Signing key not set"))
+    }
+
+    pub fn signing_key_address(&self) -> Result<String> {
+        let signer: PrivateKeySigner = self.current_signing_key()?.expose().parse().map_err(|_| eyre!("Invalid signing key"))?;
+        Ok(signer.address().to_string())
+    }
+
+    // Registers a new signing key and signs with it from now on. The api key and websocket session
+    // are kept, and the old key stays registered so the orders it signed stay open.
+    // Returns the credentials with the new key and its expiry. Only this client holds the new key, so
+    // callers have to persist them, e.g. as SIGNING_KEY, or the client signs with the old key again
+    // after a restart.
+    pub async fn rotate_signing_key(&self, lifetime: Duration) -> Result<(ClientCredentials, Timestamp)> {
+        let credentials = self.credentials.as_ref().ok_or_else(|| eyre!("Api key and/or secret are not established"))?;
+        let wallet_private_key = credentials.wallet_private_key.as_ref()
+            .ok_or_else(|| eyre!("Rotating the signing key needs the wallet private key"))?;
+
+        let signing_key = signer_key(&PrivateKeySigner::random());
+        let expiry = Utc::now() + lifetime;
+        let data = match self.rest_register(wallet_private_key, &signing_key, expiry, None).await? {
+            RestResponse::Register(data) => data,
+            response => return Err(eyre!("Unexpected register response: {:?}", response))
+        };
+        *self.rotated_key.write().map_err(|_| eyre!("Rotated signing key lock poisoned"))? = Some(signing_key.clone());
+        info!("Rotated to signing key {} until {}", self.signing_key_address()?, expiry);
+
+        // Registering also creates an api key, which is not needed while the current one works
+        if data.api_key != credentials.api_key {
+            if let Err(e) = self.rest_delete_api_key(&data.api_key).await {
+                warn!("Problem revoking the api key created with the new signing key: {}", e);
+            }
+        }
+        Ok((ClientCredentials { signing_key, ..credentials.clone() }, expiry))
+    }
+
+    // Looks the signing key up on the account, warns when it expires soon and rotates it when the
+    // config asks to. Returns the status of the key in use afterwards, and the credentials to persist
+    // when it rotated.
+    pub async fn check_signing_key(&self, config: &KeyExpiryConfig) -> Result<(KeyStatus, Option<ClientCredentials>)> {
+        let address = self.signing_key_address()?;
+        let keys = self.rest_get_signing_keys().await?;
+        let now = Utc::now();
+        let status = key_status(&keys, &address, now, config.warn_before);
+
+        match status {
+            KeyStatus::Valid(_) => {},
+            KeyStatus::Expiring(expiry) => warn!("Signing key {} expires at {}", address, expiry),
+            KeyStatus::Expired(expiry) => error!("Signing key {} expired at {}, orders will be rejected", address, expiry),
+            KeyStatus::Unknown => error!("Signing key {} is not registered with the account", address)
+        }
+
+        let rotate = match (config.rotate_before, status.expiry()) {
+            (Some(rotate_before), Some(expiry)) => expiry - now <= rotate_before,
+            _ => false
+        };
+        if rotate {
+            let (credentials, expiry) = self.rotate_signing_key(config.lifetime).await?;
+            return Ok((KeyStatus::Valid(expiry), Some(credentials)))
+        }
+        Ok((status, None))
+    }
+
+    // Checks the signing key right away and then on every interval. Credentials with a rotated key
+    // are sent to rotated for the caller to persist.
+    pub fn spawn_key_monitor(
+        client: Arc<AevoClient>,
+        config: KeyExpiryConfig,
+        rotated: Option<UnboundedSender<ClientCredentials>>
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(config.check_interval);
+            loop {
+                interval.tick().await;
+                match client.check_signing_key(&config).await {
+                    Ok((_, Some(credentials))) => match &rotated {
+                        Some(tx) => {
+                            if let Err(e) = tx.send(credentials) {
+                                error!("Problem sending the rotated credentials: {}", e);
+                            }
+                        },
+                        None => warn!("Signing key rotated but the new credentials are not persisted")
+                    },
+                    Ok(_) => {},
+                    Err(e) => error!("Problem checking the signing key: {}", e)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex},
+    time::{Duration, Instant}
+};
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use tokio::{task::JoinHandle, time::timeout};
+use crate::{aevo::AevoClient, rest::RestResponse};
+
+// Time each of the kill switch's cancels gets, a reconnecting websocket can otherwise block forever
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Shared flag consulted by the risk checks. While engaged every new order is rejected before it
+// is signed.
+#[derive(Debug, Default)]
+pub struct KillSwitch {
+    engaged : AtomicBool,
+    reason : Mutex<Option<String>>
+}
+
+impl KillSwitch {
+    pub fn new() -> KillSwitch {
+        KillSwitch::default()
+    }
+
+    // Returns false if the switch was already engaged
+    pub fn engage(&self, reason: &str) -> bool {
+        let newly_engaged = !self.engaged.swap(true, Ordering::SeqCst);
+        if newly_engaged {
+            *self.reason.lock().unwrap() = Some(reason.to_string());
+        }
+        newly_engaged
+    }
+
+    pub fn reset(&self) {
+        self.engaged.store(false, Ordering::SeqCst);
+        *self.reason.lock().unwrap() = None;
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.load(Ordering::SeqCst)
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        self.reason.lock().unwrap().clone()
+    }
+}
+
+// Tracks how long the websocket has been down, updated by the read and send loops
+#[derive(Debug, Default)]
+pub struct ConnectionStatus {
+    disconnected_since : Mutex<Option<Instant>>
+}
+
+impl ConnectionStatus {
+    pub fn new() -> ConnectionStatus {
+        ConnectionStatus::default()
+    }
+
+    pub fn mark_connected(&self) {
+        *self.disconnected_since.lock().unwrap() = None;
+    }
+
+    // Keeps the time of the first failure so repeated errors do not restart the clock
+    pub fn mark_disconnected(&self) {
+        self.disconnected_since.lock().unwrap().get_or_insert_with(Instant::now);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.disconnected_since.lock().unwrap().is_none()
+    }
+
+    pub fn disconnected_for(&self) -> Option<Duration> {
+        self.disconnected_since.lock().unwrap().map(|since| since.elapsed())
+    }
+}
+
+// Limits left as None are not monitored
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadMansSwitchConfig {
+    // Maximum time between two calls to DeadMansSwitch::heartbeat
+    pub heartbeat_timeout : Option<Duration>,
+    // Maximum time the websocket may stay disconnected
+    pub disconnect_timeout : Option<Duration>,
+    pub check_interval : Duration
+}
+
+impl Default for DeadMansSwitchConfig {
+    fn default() -> DeadMansSwitchConfig {
+        DeadMansSwitchConfig {
+            heartbeat_timeout : Some(Duration::from_secs(30)),
+            disconnect_timeout : Some(Duration::from_secs(10)),
+            check_interval : Duration::from_secs(1)
+        }
+    }
+}
+
+// Handle kept by the strategy to prove it is still alive
+#[derive(Debug, Clone)]
+pub struct DeadMansSwitch {
+    last_heartbeat : Arc<Mutex<Instant>>
+}
+
+impl DeadMansSwitch {
+    pub fn new() -> DeadMansSwitch {
+        DeadMansSwitch { last_heartbeat : Arc::new(Mutex::new(Instant::now())) }
+    }
+
+    pub fn heartbeat(&self) {
+        *self.last_heartbeat.lock().unwrap() = Instant::now();
+    }
+
+    pub fn since_heartbeat(&self) -> Duration {
+        self.last_heartbeat.lock().unwrap().elapsed()
+    }
+
+    // Reason to trip the switch, if any of the configured timeouts has passed
+    pub fn expired(&self, config: &DeadMansSwitchConfig, connection: &ConnectionStatus) -> Option<String> {
+        if let Some(timeout) = config.heartbeat_timeout {
+            let elapsed = self.since_heartbeat();
+            if elapsed > timeout {
+                return Some(format!("No heartbeat for {:?}", elapsed))
+            }
+        }
+
+        if let (Some(timeout), Some(elapsed)) = (config.disconnect_timeout, connection.disconnected_for()) {
+            if elapsed > timeout {
+                return Some(format!("Websocket disconnected for {:?}", elapsed))
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for DeadMansSwitch {
+    fn default() -> DeadMansSwitch {
+        DeadMansSwitch::new()
+    }
+}
+
+impl AevoClient {
+    // Blocks new orders and cancels everything resting over both transports at once, each within
+    // CANCEL_TIMEOUT, so a websocket that is down can not hold up the REST cancel. Only the REST reply
+    // confirms the orders are gone, a sent websocket cancel does not, so it fails unless REST succeeded.
+    pub async fn trigger_kill_switch(&self, reason: &str) -> Result<()> {
+        if self.kill_switch.engage(reason) {
+            warn!("Kill switch engaged: {}", reason);
+        }
+
+        let (ws_result, rest_result) = tokio::join!(
+            timeout(CANCEL_TIMEOUT, self.cancel_all_orders()),
+            timeout(CANCEL_TIMEOUT, self.rest_cancel_all_orders(None, None))
+        );
+
+        let ws_result = ws_result.unwrap_or_else(|_| Err(eyre!("Timed out after {:?}", CANCEL_TIMEOUT)));
+        if let Err(e) = &ws_result {
+            error!("Problem cancelling orders over websocket: {}", e);
+        }
+
+        let rest_result = match rest_result {
+            Ok(Ok(RestResponse::DeleteOrdersAll(data))) if data.success => Ok(data.order_ids),
+            Ok(Ok(response)) => Err(eyre!("Unexpected cancel response: {:?}", response)),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(eyre!("Timed out after {:?}", CANCEL_TIMEOUT))
+        };
+
+        match rest_result {
+            Ok(order_ids) => {
+                info!("Kill switch cancelled {} orders", order_ids.len());
+                Ok(())
+            },
+            Err(e) => Err(eyre!(
+                "Kill switch could not confirm orders were cancelled, REST: {}, websocket cancel {}",
+                e,
+                if ws_result.is_ok() { "sent" } else { "failed" }
+            ))
+        }
+    }
+
+    pub fn reset_kill_switch(&self) {
+        info!("Kill switch reset");
+        self.kill_switch.reset();
+    }
+
+    pub fn is_kill_switch_engaged(&self) -> bool {
+        self.kill_switch.is_engaged()
+    }
+
+    // Trips the kill switch when the returned handle misses its heartbeat or the websocket stays
+    // down past the configured timeouts. The heartbeat clock restarts after the switch is reset.
+    pub fn spawn_dead_mans_switch(client: Arc<AevoClient>, config: DeadMansSwitchConfig) -> (DeadMansSwitch, JoinHandle<()>) {
+        let handle = DeadMansSwitch::new();
+        let switch = handle.clone();
+
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(config.check_interval);
+            let mut tripped = false;
+            loop {
+                interval.tick().await;
+
+                if tripped {
+                    if !client.kill_switch.is_engaged() {
+                        tripped = false;
+                        switch.heartbeat();
+                    }
+                    continue;
+                }
+
+                // A failed cancel is retried on the next tick, new orders are blocked meanwhile
+                if let Some(reason) = switch.expired(&config, &client.connection) {
+                    warn!("Dead man's switch tripped: {}", reason);
+                    match client.trigger_kill_switch(&reason).await {
+                        Ok(()) => tripped = true,
+                        Err(e) => error!("{}", e)
+                    }
+                }
+            }
+        });
+
+        (handle, task)
+    }
+}
@@ -3,6 +3,28 @@ pub mod env;
 pub mod signature; 
 pub mod rest; 
 pub mod ws_structs;
+pub mod types;
+pub mod instruments;
+pub mod option_chain;
+pub mod analytics;
+pub mod order_manager;
+pub mod positions;
+pub mod risk;
+pub mod kill_switch;
+pub mod rate_limit;
+pub mod retry;
+pub mod batch;
+pub mod replies;
+pub mod quoting;
+pub mod mmp;
+pub mod execution;
+pub mod smart_orders;
+pub mod paper;
+pub mod recorder;
+pub mod backtest;
+pub mod credentials;
+pub mod registration;
+pub mod key_rotation;
 
 #[cfg(test)]
 mod tests {
@@ -17,13 +39,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_open_connection() { 
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -31,13 +47,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_get_index() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -55,13 +65,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_get_markets() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -79,13 +83,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_get_account() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -103,13 +101,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_get_portfolio() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -127,13 +119,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_get_open_orders() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -151,16 +137,12 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_create_order() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
+        client.load_instruments("ETH".to_string()).await.unwrap(); 
+
         let (order, order_id) = client.create_order_rest(
             1, 
             true, 
@@ -179,16 +161,12 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_open_order() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
+        client.load_instruments("ETH".to_string()).await.unwrap(); 
+
         let response = client.rest_create_order(
             1, 
             true, 
@@ -210,16 +188,12 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_open_market_order() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
+        client.load_instruments("ETH".to_string()).await.unwrap(); 
+
         let response = client.rest_create_market_order(
             1, 
             true,
@@ -238,13 +212,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_cancel_all_orders() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -265,13 +233,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_subscribe_index() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap();
 
@@ -298,13 +260,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_subscribe_fills() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -329,15 +285,36 @@ mod tests {
         join!(task1, task2); 
     }
 
+    #[test(tokio::test)]
+    async fn test_subscribe_positions() {
+        let credentials = ClientCredentials::from_env().unwrap();
+        
+        let client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<WsResponse>(); 
+
+        client.subscribe_positions().await.unwrap();
+
+        let task1 = tokio::spawn(async move {
+            client.read_messages(tx).await.unwrap()
+        });
+
+        let task2 = tokio::spawn(async move {
+            loop {
+                if let Some(data) = rx.recv().await {
+                    println!("The data: {:?}", data);
+                }
+            }
+        });  
+
+        let (result1, result2) = join!(task1, task2); 
+        result1.unwrap();
+        result2.unwrap();
+    }
+
     #[test(tokio::test)]
     async fn test_ping() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -364,13 +341,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_subscribe_orderbook() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -397,13 +368,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_subscribe_book_ticker() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -432,16 +397,12 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_ws_open_order() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
+        client.load_instruments("ETH".to_string()).await.unwrap(); 
+
         let order_id = client.create_order(
             1, 
             true, 
@@ -477,13 +438,7 @@ mod tests {
 
     #[test(tokio::test)]
     async fn test_ws_cancel_order() {
-        let credentials = ClientCredentials {
-            signing_key : std::env::var("SIGNING_KEY").unwrap(), 
-            wallet_address : std::env::var("WALLET_ADDRESS").unwrap(), 
-            api_secret : std::env::var("API_SECRET").unwrap(), 
-            api_key : std::env::var("API_KEY").unwrap(), 
-            wallet_private_key : None
-        };
+        let credentials = ClientCredentials::from_env().unwrap();
         
         let mut client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
 
@@ -491,4 +446,850 @@ mod tests {
             "0x3dbf007fc71ca02327fee4591e5a1f1fce63dc3f97d916ecfd887c46745a2820".to_string()
         ).await.unwrap(); 
     }
+
+    #[test]
+    fn test_parse_typed_fill() {
+        let msg = r#"{"channel":"fills","data":{"timestamp":"1700000000123456789","fill":{"trade_id":"0x1","order_id":"0x2","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","price":"2400.15","side":"buy","fees":"0.012","filled":"0.01","order_status":"filled","liquidity":"maker","created_timestamp":"1700000000123456789","system_type":"API"}}}"#;
+
+        let response = AevoClient::parse_response(Message::from(msg)).unwrap();
+
+        match response {
+            WsResponse::SubscribeResponse { data : ws_structs::WsResponseData::FillsData { timestamp, fill }, .. } => {
+                assert_eq!(fill.instrument_id, 1);
+                assert_eq!(fill.price, "2400.15".parse::<types::Decimal>().unwrap());
+                assert_eq!(timestamp.timestamp_nanos_opt(), Some(1700000000123456789));
+                assert_eq!(types::LegacyString::to_legacy_string(&fill.created_timestamp), "1700000000123456789");
+            }, 
+            _ => panic!("Not FillsData type: {:?}", response)
+        }
+    }
+
+    #[test]
+    fn test_instrument_rounding() {
+        let market = r#"{"instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","underlying_asset":"ETH","quote_asset":"USDC","price_step":"0.05","amount_step":"0.01","min_order_value":"10","max_order_value":"1000000","max_notional_value":"10000000","mark_price":"2400","index_price":"2400","is_active":true,"max_leverage":"20"}"#;
+
+        let mut instruments = instruments::Instruments::new(); 
+        instruments.update(vec![serde_json::from_str(market).unwrap()]); 
+
+        assert_eq!(instruments.instrument_id("ETH-PERP"), Some(1)); 
+        assert_eq!(instruments.prepare_order(1, true, Some(2400.07), 0.579).unwrap(), (Some(2400.05), 0.57)); 
+        assert_eq!(instruments.prepare_order(1, false, Some(2400.07), 0.579).unwrap(), (Some(2400.1), 0.57)); 
+        assert!(instruments.prepare_order(1, true, Some(2400.0), 0.001).is_err()); 
+        assert_eq!(aevo::to_fixed(0.57, aevo::AMOUNT_DECIMALS).unwrap(), "570000"); 
+
+        // A refresh without the instrument evicts it
+        instruments.replace_asset("ETH", vec![]); 
+        assert_eq!(instruments.instrument_id("ETH-PERP"), None); 
+        assert!(instruments.get(1).is_none()); 
+        assert!(instruments.prepare_order(1, true, Some(2400.07), 0.579).is_err()); 
+    }
+
+    #[test]
+    fn test_instrument_name_round_trip() {
+        use instruments::{InstrumentName, OptionKind};
+
+        for name in ["ETH-PERP", "ETH-27SEP24-3000-C", "BTC-6DEC24-62500-P", "DOGE-27SEP24-0.15-C"] {
+            let parsed: InstrumentName = name.parse().unwrap(); 
+            assert_eq!(parsed.to_string(), name); 
+        }
+
+        let parsed: InstrumentName = "ETH-27SEP24-3000-C".parse().unwrap(); 
+        assert_eq!(parsed.underlying, "ETH"); 
+        assert_eq!(parsed.expiry(), chrono::NaiveDate::from_ymd_opt(2024, 9, 27)); 
+        assert_eq!(parsed.strike(), Some(types::Decimal::from(3000))); 
+        assert_eq!(parsed.option_kind(), Some(OptionKind::Call)); 
+        assert!("ETH-27SEP24-3000-X".parse::<InstrumentName>().is_err()); 
+        assert!("BTC-06DEC24-62500-P".parse::<InstrumentName>().is_err()); 
+    }
+
+    #[test]
+    fn test_option_chain() {
+        let option = |id: u64, strike: u64, option_type: &str, mark: &str, delta: &str, iv: &str| {
+            let json = format!(
+                r#"{{"instrument_id":"{id}","instrument_name":"ETH-27SEP24-{strike}-{}","instrument_type":"OPTION","underlying_asset":"ETH","quote_asset":"USDC","price_step":"0.1","amount_step":"0.01","min_order_value":"10","max_order_value":"1000000","max_notional_value":"10000000","mark_price":"{mark}","forward_price":"2500","index_price":"2490","is_active":true,"option_type":"{option_type}","expiry":"1727424000000000000","strike":"{strike}","greeks":{{"delta":"{delta}","theta":"-1","gamma":"0.001","rho":"0.1","vega":"2","iv":"{iv}"}}}}"#,
+                if option_type == "call" { "C" } else { "P" }
+            );
+            serde_json::from_str::<rest::MarketInfo>(&json).unwrap()
+        };
+
+        let markets = vec![
+            option(1, 2400, "call", "160", "0.65", "0.62"), 
+            option(2, 2400, "put", "40", "-0.35", "0.64"), 
+            option(3, 2600, "call", "50", "0.25", "0.58"), 
+            option(4, 2600, "put", "155", "-0.75", "0.66"), 
+        ];
+
+        let chain = option_chain::OptionChain::from_markets("ETH", &markets); 
+        let expiry = chrono::NaiveDate::from_ymd_opt(2024, 9, 27).unwrap(); 
+
+        assert_eq!(chain.implied_forward(expiry), Some(types::Decimal::from(2495))); 
+        assert_eq!(chain.atm_strike(expiry), Some(types::Decimal::from(2400))); 
+        assert_eq!(chain.skew(expiry, "0.25".parse().unwrap()), Some("-0.06".parse().unwrap())); 
+        assert_eq!(chain.term_structure(), vec![(expiry, "0.63".parse().unwrap())]); 
+    }
+
+    #[test]
+    fn test_black_scholes() {
+        use analytics::OptionParams;
+        use instruments::OptionKind;
+
+        let call = OptionParams { option_kind : OptionKind::Call, spot : 100.0, strike : 100.0, time : 1.0, volatility : 0.2, rate : 0.05 }; 
+        let put = OptionParams { option_kind : OptionKind::Put, ..call }; 
+
+        assert!((call.price() - 10.4506).abs() < 1e-3); 
+        assert!((put.price() - 5.5735).abs() < 1e-3); 
+        assert!((call.greeks().delta - 0.6368).abs() < 1e-3); 
+        assert!((call.greeks().delta - put.greeks().delta - 1.0).abs() < 1e-9); 
+
+        let solved = OptionParams { volatility : 0.0, ..put }.implied_volatility(put.price()).unwrap(); 
+        assert!((solved - 0.2).abs() < 1e-6); 
+        assert!(call.implied_volatility(150.0).is_err()); 
+    }
+
+    #[test]
+    fn test_order_manager_transitions() {
+        use order_manager::{OrderManager, OrderState, Submission};
+
+        let mut manager = OrderManager::new(); 
+        let mut events = manager.subscribe(); 
+
+        manager.record_submission(Submission { order_id : "0xa".to_string(), instrument_id : 1, is_buy : true, price : Some(types::Decimal::from(2400)), amount : "0.02".parse().unwrap(), request_id : Some(7), replaces : None }); 
+        manager.record_submission(Submission { order_id : "0xb".to_string(), instrument_id : 1, is_buy : true, price : Some(types::Decimal::from(2300)), amount : "0.02".parse().unwrap(), request_id : Some(8), replaces : None }); 
+
+        let ack = r#"{"id":7,"data":{"order_id":"0xa","account":"0x1","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","order_type":"limit","order_status":"opened","side":"buy","amount":"0.02","price":"2400","filled":"0","initial_margin":"5","created_timestamp":"1700000000000000000","timestamp":"1700000000000000000","system_type":"API"}}"#;
+        let fill = |trade_id: &str, status: &str| format!(
+            r#"{{"channel":"fills","data":{{"timestamp":"1700000000000000000","fill":{{"trade_id":"{trade_id}","order_id":"0xa","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","price":"2400","side":"buy","fees":"0.01","filled":"0.01","order_status":"{status}","liquidity":"maker","created_timestamp":"1700000000000000000","system_type":"API"}}}}}}"#
+        );
+        let reject = r#"{"id":8,"error":"INSUFFICIENT_MARGIN"}"#;
+
+        for msg in [ack.to_string(), fill("t1", "partial"), fill("t1", "partial"), fill("t2", "filled"), reject.to_string()] {
+            manager.apply(&AevoClient::parse_response(Message::from(msg)).unwrap()); 
+        }
+
+        let order = manager.get("0xa").unwrap(); 
+        assert_eq!(order.state, OrderState::Filled); 
+        assert_eq!(order.filled, "0.02".parse().unwrap()); 
+        assert_eq!(manager.get("0xb").unwrap().state, OrderState::Rejected); 
+        assert!(manager.open_orders().is_empty()); 
+
+        let mut states = vec![]; 
+        while let Ok(event) = events.try_recv() {
+            if event.order.order_id == "0xa" {
+                states.push(event.order.state); 
+            }
+        }
+        assert_eq!(states, vec![OrderState::Pending, OrderState::Acknowledged, OrderState::PartiallyFilled, OrderState::Filled]); 
+    }
+
+    #[test]
+    fn test_position_tracker_pnl() {
+        let fill = |trade_id: &str, side: &str, price: &str, filled: &str| format!(
+            r#"{{"channel":"fills","data":{{"timestamp":"1700000000000000000","fill":{{"trade_id":"{trade_id}","order_id":"0xa","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","price":"{price}","side":"{side}","fees":"0.5","filled":"{filled}","order_status":"filled","liquidity":"taker","created_timestamp":"1700000000000000000","system_type":"API"}}}}}}"#
+        );
+
+        let mut tracker = positions::PositionTracker::new(); 
+        for msg in [fill("t1", "buy", "2000", "1"), fill("t2", "buy", "2100", "1"), fill("t2", "buy", "2100", "1"), fill("t3", "sell", "2200", "3")] {
+            tracker.apply(&AevoClient::parse_response(Message::from(msg)).unwrap()); 
+        }
+        tracker.update_mark(1, types::Decimal::from(2100)); 
+
+        let position = tracker.get(1).unwrap(); 
+        assert_eq!(position.size, types::Decimal::from(-1)); 
+        assert_eq!(position.avg_entry_price, types::Decimal::from(2200)); 
+        assert_eq!(position.realized_pnl, types::Decimal::from(300)); 
+        assert_eq!(position.unrealized_pnl(), types::Decimal::from(100)); 
+        assert_eq!(position.fees, "1.5".parse().unwrap()); 
+    }
+
+    #[test]
+    fn test_risk_checks() {
+        use risk::{OrderRisk, RiskLimits, RiskManager, RiskRejection};
+
+        let mut risk = RiskManager::new(RiskLimits {
+            max_order_notional : Some(types::Decimal::from(10_000)), 
+            max_position_per_instrument : Some(types::Decimal::from(2)), 
+            price_band : Some("0.05".parse().unwrap()), 
+            max_orders_per_minute : Some(2), 
+            ..RiskLimits::default()
+        }); 
+        risk.update_reference(1, types::Decimal::from(2400)); 
+
+        let order = OrderRisk {
+            instrument_id : 1, 
+            asset : Some("ETH".to_string()), 
+            is_buy : true, 
+            price : Some(types::Decimal::from(2400)), 
+            amount : types::Decimal::ONE, 
+            position : types::Decimal::ZERO, 
+            resting : types::Decimal::ZERO, 
+            asset_notional : types::Decimal::ZERO, 
+            open_orders : 0, 
+            is_edit : false
+        }; 
+
+        assert!(matches!(risk.check(&OrderRisk { amount : types::Decimal::from(1000), ..order.clone() }), Err(RiskRejection::OrderNotional { .. }))); 
+        assert!(matches!(risk.check(&OrderRisk { position : types::Decimal::from(2), ..order.clone() }), Err(RiskRejection::InstrumentPosition { .. }))); 
+        assert!(matches!(risk.check(&OrderRisk { price : Some(types::Decimal::from(2600)), ..order.clone() }), Err(RiskRejection::PriceBand { .. }))); 
+        assert!(matches!(risk.check(&OrderRisk { instrument_id : 9, price : None, ..order.clone() }), Err(RiskRejection::UnknownPrice { instrument_id : 9 }))); 
+        assert!(risk.check(&order).is_ok()); 
+        assert!(risk.check(&order).is_ok()); 
+        assert!(matches!(risk.check(&order), Err(RiskRejection::OrderRate { count : 2, limit : 2 }))); 
+
+        // Orders checked before any of them is tracked count against the limits together
+        let mut risk = RiskManager::new(RiskLimits {
+            max_position_per_instrument : Some(types::Decimal::from(2)), 
+            max_open_orders : Some(3), 
+            ..RiskLimits::default()
+        }); 
+        let first = risk.check(&order).unwrap(); 
+        let second = risk.check(&order).unwrap(); 
+        assert!(matches!(risk.check(&order), Err(RiskRejection::InstrumentPosition { .. }))); 
+        assert!(risk.check(&OrderRisk { instrument_id : 2, ..order.clone() }).is_ok()); 
+        assert!(risk.check(&OrderRisk { instrument_id : 3, ..order.clone() }).is_ok()); 
+        let third = risk.check(&OrderRisk { instrument_id : 4, ..order.clone() }).unwrap(); 
+        assert!(matches!(risk.check(&OrderRisk { instrument_id : 5, ..order.clone() }), Err(RiskRejection::OpenOrders { open : 3, limit : 3 }))); 
+        drop((first, second, third)); 
+        assert!(risk.check(&order).is_ok()); 
+    }
+
+    #[test]
+    fn test_kill_switch() {
+        use kill_switch::{ConnectionStatus, DeadMansSwitch, DeadMansSwitchConfig, KillSwitch};
+        use std::time::Duration;
+        use tokio_tungstenite::tungstenite::Error;
+
+        let switch = KillSwitch::new(); 
+        assert!(!switch.is_engaged()); 
+        assert!(switch.engage("manual")); 
+        assert!(!switch.engage("again")); 
+        assert_eq!(switch.reason(), Some("manual".to_string())); 
+        switch.reset(); 
+        assert!(!switch.is_engaged() && switch.reason().is_none()); 
+
+        let connection = ConnectionStatus::new(); 
+        let config = DeadMansSwitchConfig {
+            heartbeat_timeout : Some(Duration::from_millis(20)), 
+            disconnect_timeout : Some(Duration::from_millis(20)), 
+            check_interval : Duration::from_millis(5)
+        }; 
+        let dead_mans_switch = DeadMansSwitch::new(); 
+        assert!(dead_mans_switch.expired(&config, &connection).is_none()); 
+
+        connection.mark_disconnected(); 
+        std::thread::sleep(Duration::from_millis(30)); 
+        dead_mans_switch.heartbeat(); 
+        assert!(dead_mans_switch.expired(&config, &connection).unwrap().starts_with("Websocket disconnected")); 
+
+        connection.mark_connected(); 
+        assert!(dead_mans_switch.expired(&config, &connection).is_none()); 
+        std::thread::sleep(Duration::from_millis(30)); 
+        assert!(dead_mans_switch.expired(&config, &connection).unwrap().starts_with("No heartbeat")); 
+
+        // Resets and other io errors end the stream like a close does, a bad message does not
+        assert!(aevo::ends_stream(&Error::Io(std::io::ErrorKind::ConnectionReset.into()))); 
+        assert!(aevo::ends_stream(&Error::ConnectionClosed)); 
+        assert!(!aevo::ends_stream(&Error::Utf8)); 
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        use rate_limit::{BucketConfig, RateLimitClass, TokenBucket};
+        use std::time::{Duration, Instant};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let start = Instant::now(); 
+        let mut bucket = TokenBucket::new(BucketConfig { capacity : 2.0, refill_per_second : 4.0 }, start); 
+        assert!(bucket.try_acquire(start).is_ok()); 
+        assert!(bucket.try_acquire(start).is_ok()); 
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_millis(250))); 
+        assert!(bucket.try_acquire(start + Duration::from_millis(250)).is_ok()); 
+
+        let now = start + Duration::from_secs(10); 
+        bucket.block_for(Duration::from_secs(2), now); 
+        assert_eq!(bucket.try_acquire(now + Duration::from_secs(1)), Err(Duration::from_secs(1))); 
+        assert!(bucket.try_acquire(now + Duration::from_millis(2250)).is_ok()); 
+
+        let class = |text: &str| RateLimitClass::of_ws_message(&Message::from(text)); 
+        assert_eq!(class(r#"{"op":"create_order","data":{}}"#), RateLimitClass::Order); 
+        assert_eq!(class(r#"{"op":"subscribe","data":["fills"]}"#), RateLimitClass::Private); 
+        assert_eq!(class(r#"{"op":"subscribe","data":["ticker:ETH:PERPETUAL"]}"#), RateLimitClass::Public); 
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        use retry::{is_transient_status, RetryPolicy};
+        use std::time::Duration;
+
+        let policy = RetryPolicy {
+            max_retries : 5, 
+            initial_backoff : Duration::from_millis(100), 
+            max_backoff : Duration::from_millis(500), 
+            multiplier : 2.0, 
+            jitter : 0.0
+        }; 
+        assert_eq!(policy.backoff(0), Duration::from_millis(100)); 
+        assert_eq!(policy.backoff(2), Duration::from_millis(400)); 
+        assert_eq!(policy.backoff(4), Duration::from_millis(500)); 
+        assert_eq!(RetryPolicy::none().max_retries, 0); 
+
+        assert!(is_transient_status(reqwest::StatusCode::BAD_GATEWAY)); 
+        assert!(!is_transient_status(reqwest::StatusCode::BAD_REQUEST)); 
+        assert!(!retry::is_transient_error(&eyre::eyre!("Invalid order"))); 
+    }
+
+    #[test(tokio::test)]
+    async fn test_batch_orders() {
+        use batch::OrderRequest;
+
+        let credentials = ClientCredentials::from_env().unwrap();
+        
+        let client = AevoClient::new(Some(credentials), env::ENV::MAINNET).await.unwrap(); 
+
+        client.load_instruments("ETH".to_string()).await.unwrap(); 
+
+        let orders = vec![
+            OrderRequest::new(1, true, 2000.0, 0.01), 
+            OrderRequest::new(1, true, 2100.0, 0.01)
+        ]; 
+        let results = client.rest_create_orders(&orders, None).await; 
+        assert_eq!(results.len(), orders.len()); 
+
+        let order_ids: Vec<String> = results.into_iter()
+            .map(|result| match result.unwrap() {
+                RestResponse::CreateOrder(data) => data.order_id, 
+                response => panic!("Not CreateOrder type: {:?}", response)
+            })
+            .collect(); 
+
+        for result in client.rest_cancel_orders(&order_ids).await {
+            result.unwrap(); 
+        }
+    }
+
+    #[test]
+    fn test_mass_cancel_reply() {
+        use replies::PendingReplies;
+        use ws_structs::{WsRequestData, WsResponse, WsResponseData};
+
+        let data = WsRequestData::CancelAllOrdersData { instrument_type : None, asset : Some("ETH".to_string()), instrument : None }; 
+        assert_eq!(serde_json::to_string(&data).unwrap(), r#"{"asset":"ETH"}"#); 
+
+        let replies = PendingReplies::new(); 
+        let id = replies.next_id(); 
+        let mut rx = replies.register(id); 
+
+        let other = serde_json::from_str::<WsResponse>(r#"{"id":1,"data":{"success":true,"order_ids":[]}}"#).unwrap(); 
+        assert!(!replies.resolve(&other)); 
+
+        let reply = serde_json::from_str::<WsResponse>(&format!(r#"{{"id":{},"data":{{"success":true,"order_ids":["0x1","0x2"]}}}}"#, id)).unwrap(); 
+        assert!(replies.resolve(&reply)); 
+        match rx.try_recv().unwrap() {
+            WsResponse::PublishResponse { data : WsResponseData::CancelAllOrdersData { order_ids, .. }, .. } => {
+                assert_eq!(order_ids, vec!["0x1".to_string(), "0x2".to_string()])
+            }, 
+            response => panic!("Unexpected reply: {:?}", response)
+        }
+    }
+
+    #[test]
+    fn test_quote_planning() {
+        use quoting::{plan_quote, quote_target, ActiveQuote, QuoteAction, QuoteParams, QuoteSide};
+        use order_manager::OrderState;
+        use instruments::Instrument;
+        use types::Decimal;
+        use std::str::FromStr;
+
+        let d = |s: &str| Decimal::from_str(s).unwrap(); 
+        let instrument = Instrument {
+            instrument_id : 1, 
+            instrument_name : "ETH-PERP".to_string(), 
+            instrument_type : "PERPETUAL".to_string(), 
+            underlying_asset : "ETH".to_string(), 
+            price_step : d("0.1"), 
+            amount_step : d("0.01"), 
+            min_order_value : d("10"), 
+            max_order_value : d("1000000"), 
+            max_leverage : None, 
+            expiry : None, 
+            is_active : true
+        }; 
+        let mut params = QuoteParams::new(1, d("1.23"), d("0.555")); 
+        params.requote_threshold = d("0.5"); 
+
+        assert_eq!(quote_target(&params, QuoteSide::Bid, d("2400"), Some(&instrument), 0), Some((d("2398.7"), d("0.55")))); 
+        assert_eq!(quote_target(&params, QuoteSide::Ask, d("2400"), Some(&instrument), 0), Some((d("2401.3"), d("0.55")))); 
+        assert_eq!(quote_target(&params, QuoteSide::Ask, d("2400"), Some(&instrument), 2), Some((d("2401.5"), d("0.55")))); 
+
+        let quote = ActiveQuote::new("0x1".to_string(), d("2398.7"), d("0.55")); 
+        assert_eq!(plan_quote(None, Some((d("2398.7"), d("0.55"))), d("0.5")), QuoteAction::Place { price : d("2398.7"), size : d("0.55") }); 
+        assert_eq!(plan_quote(Some(&quote), Some((d("2398.9"), d("0.55"))), d("0.5")), QuoteAction::Keep); 
+        assert_eq!(
+            plan_quote(Some(&quote), Some((d("2399.3"), d("0.55"))), d("0.5")), 
+            QuoteAction::Replace { order_id : "0x1".to_string(), price : d("2399.3"), size : d("0.55") }
+        ); 
+        let partial = ActiveQuote { remaining : d("0.2"), ..quote.clone() }; 
+        assert!(matches!(plan_quote(Some(&partial), Some((d("2398.7"), d("0.55"))), d("0.5")), QuoteAction::Replace { .. })); 
+        assert_eq!(plan_quote(Some(&quote), None, d("0.5")), QuoteAction::Cancel { order_id : "0x1".to_string() }); 
+
+        // An edit in flight is not moved again, and a rejected edit falls back to the resting quote
+        let edit = ActiveQuote { replaces : Some(Box::new(quote.clone())), ..ActiveQuote::new("0x2".to_string(), d("2399.3"), d("0.55")) }; 
+        assert_eq!(plan_quote(Some(&edit), Some((d("2401"), d("0.55"))), d("0.5")), QuoteAction::Keep); 
+        assert_eq!(edit.clone().follow("0x2", OrderState::Rejected, d("0.55")), Some(quote.clone())); 
+        assert_eq!(edit.clone().follow("0x2", OrderState::Acknowledged, d("0.55")).unwrap().replaces, None); 
+        assert_eq!(edit.clone().follow("0x1", OrderState::Filled, d("0")).unwrap().follow("0x2", OrderState::Rejected, d("0.55")), None); 
+    }
+
+    #[test]
+    fn test_mmp_events() {
+        use mmp::MmpTracker;
+        use ws_structs::WsResponse;
+
+        let mut tracker = MmpTracker::new(); 
+        let mut events = tracker.subscribe(); 
+
+        let frozen = serde_json::from_str::<WsResponse>(
+            r#"{"channel":"mmp","data":{"asset":"ETH","frozen":true,"frozen_end_time":"4102444800000000000"}}"#
+        ).unwrap(); 
+        tracker.apply(&frozen); 
+        assert!(tracker.is_frozen("ETH")); 
+        assert!(!tracker.is_frozen("BTC")); 
+        assert_eq!(tracker.get("ETH").unwrap().triggers, 1); 
+        assert_eq!(events.try_recv().unwrap().asset, "ETH"); 
+
+        tracker.apply(&frozen); 
+        assert_eq!(tracker.get("ETH").unwrap().triggers, 1); 
+
+        tracker.mark_reset("ETH"); 
+        assert!(!tracker.is_frozen("ETH")); 
+
+        let data = serde_json::from_str::<Vec<rest::MmpData>>(
+            r#"[{"asset":"BTC","interval":10000,"frozen":5000,"amount_limit":"10","delta_limit":"5","vega_limit":"1000"}]"#
+        ).unwrap(); 
+        tracker.apply_mmp_data(&data); 
+        assert!(!tracker.is_frozen("BTC")); 
+
+        // A freeze whose end time passed is over without an unfreeze event, one without an end
+        // time lasts until reset
+        let expired = serde_json::from_str::<WsResponse>(
+            r#"{"channel":"mmp","data":{"asset":"SOL","frozen":true,"frozen_end_time":"1722988800000000000"}}"#
+        ).unwrap(); 
+        tracker.apply(&expired); 
+        assert!(tracker.get("SOL").unwrap().frozen); 
+        assert!(!tracker.is_frozen("SOL")); 
+        let until_reset = serde_json::from_str::<WsResponse>(r#"{"channel":"mmp","data":{"asset":"SOL","frozen":true}}"#).unwrap(); 
+        tracker.apply(&until_reset); 
+        assert!(tracker.is_frozen("SOL")); 
+    }
+
+    #[test]
+    fn test_algo_slicing() {
+        use execution::{child_quantity, AlgoKind, AlgoParams};
+        use types::Decimal;
+        use std::{str::FromStr, time::Duration};
+
+        let d = |s: &str| Decimal::from_str(s).unwrap(); 
+        let twap = AlgoParams {
+            instrument_id : 1, 
+            is_buy : true, 
+            quantity : d("10"), 
+            kind : AlgoKind::Twap { duration : Duration::from_secs(100) }, 
+            interval : Duration::from_secs(10), 
+            limit_price : None, 
+            max_participation : None, 
+            min_child : d("0.01")
+        }; 
+        assert_eq!(child_quantity(&twap, Duration::from_secs(10), d("0"), d("0")), d("1")); 
+        assert_eq!(child_quantity(&twap, Duration::from_secs(50), d("4.5"), d("0")), d("0.5")); 
+        assert_eq!(child_quantity(&twap, Duration::from_secs(200), d("9"), d("0")), d("1")); 
+        assert_eq!(child_quantity(&twap, Duration::from_secs(10), d("1"), d("0")), d("0")); 
+
+        assert!(!twap.needs_trades()); 
+        let capped = AlgoParams { max_participation : Some(d("0.1")), ..twap.clone() }; 
+        assert!(capped.needs_trades()); 
+        assert_eq!(child_quantity(&capped, Duration::from_secs(50), d("0"), d("12.345")), d("1.23")); 
+
+        let vwap = AlgoParams { kind : AlgoKind::Vwap { duration : Duration::from_secs(100), participation : d("0.2") }, ..twap }; 
+        assert_eq!(child_quantity(&vwap, Duration::from_secs(10), d("0"), d("7")), d("1.4")); 
+        assert_eq!(child_quantity(&vwap, Duration::from_secs(10), d("9.5"), d("7")), d("0.5")); 
+        assert_eq!(child_quantity(&vwap, Duration::from_secs(10), d("0"), d("0.01")), d("0")); 
+    }
+
+    #[test]
+    fn test_chase_pricing() {
+        use smart_orders::{chase_price, follow_child, more_aggressive, WorkingOrder};
+        use order_manager::OrderState;
+        use types::Decimal;
+
+        let (bid, ask) = (Decimal::from(2400), Decimal::from(2401)); 
+        assert_eq!(chase_price(true, bid, ask), Some(bid)); 
+        assert_eq!(chase_price(false, bid, ask), Some(ask)); 
+        assert_eq!(chase_price(true, Decimal::ZERO, ask), None); 
+
+        assert!(more_aggressive(true, ask, bid)); 
+        assert!(!more_aggressive(true, bid, ask)); 
+        assert!(more_aggressive(false, bid, ask)); 
+        assert!(!more_aggressive(false, bid, bid)); 
+
+        // A rejected edit falls back to the order it was replacing, which is still resting
+        let old = WorkingOrder { order_id : "0xa".to_string(), price : bid, replaces : None }; 
+        let edit = || Some(WorkingOrder { order_id : "0xb".to_string(), price : ask, replaces : Some(Box::new(old.clone())) }); 
+        assert_eq!(follow_child(edit(), "0xb", OrderState::Rejected), Some(old.clone())); 
+        assert_eq!(follow_child(edit(), "0xb", OrderState::Acknowledged).unwrap().replaces, None); 
+        // Unless the old order filled in the meantime
+        let filled = follow_child(edit(), "0xa", OrderState::Filled); 
+        assert_eq!(follow_child(filled, "0xb", OrderState::Rejected), None); 
+        assert_eq!(follow_child(Some(old.clone()), "0xa", OrderState::Cancelled), None); 
+    }
+
+    #[test]
+    fn test_paper_matching() {
+        use paper::{PaperConfig, PaperExchange, PaperOrderRequest};
+        use types::Decimal;
+        use ws_structs::{WsResponse, WsResponseData};
+        use std::str::FromStr;
+
+        let d = |s: &str| Decimal::from_str(s).unwrap(); 
+        let book = |kind: &str, bids: &str, asks: &str| serde_json::from_str::<WsResponse>(&format!(
+            r#"{{"channel":"orderbook:ETH-PERP","data":{{"type":"{}","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","bids":{},"asks":{},"last_updated":"1722988800000000000","checksum":"0"}}}}"#,
+            kind, bids, asks
+        )).unwrap(); 
+        let order = |order_id: &str, is_buy: bool, price: Option<&str>, amount: &str, post_only: bool| PaperOrderRequest {
+            order_id : order_id.to_string(), 
+            instrument_id : 1, 
+            instrument_name : "ETH-PERP".to_string(), 
+            instrument_type : "PERPETUAL".to_string(), 
+            asset : "ETH".to_string(), 
+            is_buy, 
+            price : price.map(d), 
+            amount : d(amount), 
+            post_only, 
+            ioc : false
+        }; 
+        let fills = |messages: &[WsResponse]| messages.iter().filter_map(|m| match m {
+            WsResponse::SubscribeResponse { data : WsResponseData::FillsData { fill, .. }, .. } => Some((fill.price, fill.filled, fill.liquidity.clone())), 
+            _ => None
+        }).collect::<Vec<_>>(); 
+
+        let mut exchange = PaperExchange::new(PaperConfig::default()); 
+        exchange.apply_market(&book("snapshot", r#"[["99","1"]]"#, r#"[["101","1"],["102","2"]]"#)); 
+
+        // Crossing post-only orders are rejected, market orders without a book too
+        assert!(exchange.submit(order("a", true, Some("101"), "1", true)).is_err()); 
+        let mut other = order("x", true, None, "1", false); 
+        other.instrument_id = 2; 
+        assert!(exchange.submit(other).is_err()); 
+
+        // Takes both ask levels up to the limit and rests the remainder
+        let messages = exchange.submit(order("b", true, Some("102"), "4", false)).unwrap(); 
+        assert_eq!(fills(&messages), vec![(d("101"), d("1"), "taker".to_string()), (d("102"), d("2"), "taker".to_string())]); 
+        let data = exchange.order_data("b").unwrap(); 
+        assert_eq!(data.order_status, "partial"); 
+        assert_eq!(data.filled, d("3")); 
+        assert_eq!(exchange.open_orders().len(), 1); 
+        let positions = messages.iter().find_map(|m| match m {
+            WsResponse::SubscribeResponse { data : WsResponseData::PositionsData { positions, .. }, .. } => Some(positions.clone()), 
+            _ => None
+        }).unwrap(); 
+        assert_eq!(positions[0].amount, d("3")); 
+        assert_eq!(positions[0].side, "buy"); 
+
+        // The rest fills at its own price once the book trades through it
+        let messages = exchange.apply_market(&book("update", "[]", r#"[["100","5"]]"#)); 
+        assert_eq!(fills(&messages), vec![(d("102"), d("1"), "maker".to_string())]); 
+        assert_eq!(exchange.order_data("b").unwrap().order_status, "filled"); 
+        assert!(exchange.open_orders().is_empty()); 
+
+        // Resting sell, an edit that would cross is rejected and leaves it open, then cancelled
+        exchange.submit(order("c", false, Some("110"), "1", true)).unwrap(); 
+        assert!(exchange.edit("c", order("d", false, Some("99"), "1", true)).is_err()); 
+        assert_eq!(exchange.order_data("c").unwrap().order_status, "opened"); 
+        assert!(exchange.order_data("d").is_none()); 
+        assert!(exchange.cancel("c").is_ok()); 
+        assert!(exchange.cancel("c").is_err()); 
+    }
+
+    #[test]
+    fn test_recorder_rotation() {
+        use recorder::{read_ndjson, Recorder, RecorderConfig};
+        use ws_structs::WsResponse;
+        use chrono::{TimeZone, Utc};
+
+        let directory = std::env::temp_dir().join(format!("aevo-recorder-{}", rand::random::<u64>())); 
+        let (recorder, writer) = Recorder::start(RecorderConfig::new(&directory)).unwrap(); 
+
+        let trade = serde_json::from_str::<WsResponse>(
+            r#"{"channel":"trades:ETH-PERP","write_ts":"1722988800000000000","data":{"trade_id":"1","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","side":"buy","price":"2500","amount":"1","created_timestamp":"1722988800000000000"}}"#
+        ).unwrap(); 
+        let index = serde_json::from_str::<WsResponse>(
+            r#"{"channel":"index:ETH","data":{"price":"2500","timestamp":"1722988800000000000"}}"#
+        ).unwrap(); 
+        let status = serde_json::from_str::<WsResponse>(
+            r#"{"id":1,"data":{"account":"0x1","subscriptions":[]}}"#
+        ).unwrap(); 
+
+        let first_hour = Utc.with_ymd_and_hms(2024, 8, 7, 0, 10, 0).unwrap(); 
+        let second_hour = Utc.with_ymd_and_hms(2024, 8, 7, 1, 5, 0).unwrap(); 
+        recorder.record(&trade, first_hour); 
+        recorder.record(&status, first_hour); 
+        recorder.record(&index, first_hour); 
+        recorder.record(&trade, second_hour); 
+        drop(recorder); 
+        writer.join().unwrap().unwrap(); 
+
+        let first = read_ndjson(directory.join("20240807-00.ndjson.gz")).unwrap(); 
+        assert_eq!(first.len(), 2); 
+        assert_eq!(first[0].channel, "trades:ETH-PERP"); 
+        assert_eq!(first[0].received, first_hour); 
+        assert_eq!(first[0].exchange_ts, Some(Utc.timestamp_nanos(1722988800000000000))); 
+        assert_eq!(first[1].exchange_ts, None); 
+        assert!(matches!(first[0].message, WsResponse::SubscribeResponse { .. })); 
+
+        let second = read_ndjson(directory.join("20240807-01.ndjson.gz")).unwrap(); 
+        assert_eq!(second.len(), 1); 
+        std::fs::remove_dir_all(&directory).unwrap(); 
+
+        // A writer that cannot create its file stops, keeps its error and later messages are dropped
+        let (recorder, writer) = Recorder::start(RecorderConfig::new(&directory)).unwrap(); 
+        std::fs::remove_dir_all(&directory).unwrap(); 
+        recorder.record(&trade, first_hour); 
+        assert!(writer.join().unwrap().is_err()); 
+        assert!(recorder.error().is_some()); 
+        recorder.record(&trade, first_hour); 
+        recorder.record(&trade, first_hour); 
+        assert_eq!(recorder.dropped(), 2); 
+    }
+
+    #[test]
+    fn test_backtest_replay() {
+        use backtest::{Backtester, BacktestConfig, BacktestContext};
+        use batch::OrderRequest;
+        use recorder::RecordedMessage;
+        use types::Decimal;
+        use ws_structs::{WsResponse, WsResponseData};
+        use chrono::{TimeZone, Utc};
+        use std::str::FromStr;
+
+        let d = |s: &str| Decimal::from_str(s).unwrap(); 
+        let start = 1722988800000000000i64; 
+        let record = |offset_ms: i64, channel: &str, data: &str| {
+            let ts = start + offset_ms * 1_000_000; 
+            let message = serde_json::from_str::<WsResponse>(&format!(
+                r#"{{"channel":"{}","write_ts":"{}","data":{}}}"#, channel, ts, data.replace("TS", &ts.to_string())
+            )).unwrap(); 
+            RecordedMessage { channel : channel.to_string(), exchange_ts : Some(Utc.timestamp_nanos(ts)), received : Utc.timestamp_nanos(ts), message }
+        }; 
+        let book = |offset_ms: i64, bid: &str, ask: &str| record(offset_ms, "orderbook:ETH-PERP", &format!(
+            r#"{{"type":"snapshot","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","bids":[["{}","2"]],"asks":[["{}","1"]],"last_updated":"TS","checksum":"0"}}"#, bid, ask
+        )); 
+        let trade = |offset_ms: i64, amount: &str| record(offset_ms, "trades:ETH-PERP", &format!(
+            r#"{{"trade_id":"t{}","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","side":"sell","price":"100","amount":"{}","created_timestamp":"TS"}}"#, offset_ms, amount
+        )); 
+
+        // Recorded out of order on purpose, the replay sorts them
+        let records = vec![
+            book(3000, "110", "111"), 
+            book(0, "100", "101"), 
+            // Before the order reaches the exchange
+            trade(10, "5"), 
+            // Trades through the 2 queued ahead of the order
+            trade(1000, "3"), 
+            // A fill of the recording account, not replayed
+            record(2000, "fills", r#"{"timestamp":"TS","fill":{"trade_id":"0x1","order_id":"0x2","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","price":"105","side":"buy","fees":"0.01","filled":"1","order_status":"filled","liquidity":"taker","created_timestamp":"TS","system_type":"API"}}"#)
+        ]; 
+
+        let mut placed = false; 
+        let mut fills = Vec::new(); 
+        let mut strategy = |message: &WsResponse, ctx: &mut BacktestContext| {
+            match message {
+                WsResponse::SubscribeResponse { data : WsResponseData::OrderBookData { .. }, .. } if !placed => {
+                    ctx.create_order(&OrderRequest::new(1, true, 100.0, 1.0)).unwrap(); 
+                    placed = true; 
+                },
+                WsResponse::SubscribeResponse { data : WsResponseData::FillsData { fill, .. }, .. } => fills.push((ctx.now(), fill.price)), 
+                _ => {}
+            }
+        }; 
+
+        let result = Backtester::new(BacktestConfig::default()).unwrap().run(records, &mut strategy); 
+        assert_eq!(result.trades.len(), 1); 
+        assert_eq!(result.trades[0].liquidity, "maker"); 
+        assert_eq!(result.trades[0].fees, d("0.03")); 
+        // The fill reaches the strategy one latency after the trade
+        assert_eq!(fills, vec![(Utc.timestamp_nanos(start + 1_050_000_000), d("100"))]); 
+
+        assert_eq!(result.summary.fills, 1); 
+        assert_eq!(result.summary.unrealized_pnl, d("10.5")); 
+        assert_eq!(result.summary.net_pnl, d("10.47")); 
+        assert_eq!(result.summary.max_drawdown, Decimal::ZERO); 
+        assert_eq!(result.pnl_curve.last().unwrap().equity, d("10.47")); 
+    }
+
+    #[test]
+    fn test_credentials_loading() {
+        use alloy::signers::local::PrivateKeySigner;
+
+        let directory = std::env::temp_dir().join(format!("aevo-credentials-{}", rand::random::<u64>())); 
+        std::fs::create_dir_all(&directory).unwrap(); 
+        let signing_key = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"; 
+        let wallet_key = "0x8da4ef21b864d2cc526dbdb2a120bd2874c36c9d0a1fb7f8c63d7f7a8b41de8f"; 
+
+        let dotenv = directory.join(".env"); 
+        std::fs::write(&dotenv, format!("SIGNING_KEY={}\nWALLET_ADDRESS=0x1\nAPI_KEY=key\nAPI_SECRET=\"s3cr3t\"\n", signing_key)).unwrap(); 
+        let credentials = ClientCredentials::from_file(&dotenv).unwrap(); 
+        assert_eq!(credentials.signing_key.expose(), signing_key); 
+        assert_eq!(credentials.api_secret.expose(), "s3cr3t"); 
+        assert!(credentials.wallet_private_key.is_none()); 
+
+        // Redacted from Debug
+        let debug = format!("{:?}", credentials); 
+        assert!(!debug.contains(&signing_key[2..]) && !debug.contains("s3cr3t")); 
+        assert!(debug.contains("0x1") && debug.contains("key")); 
+
+        // Wallet key from a keystore, the address follows from it
+        let bytes: Vec<u8> = (0..32).map(|i| u8::from_str_radix(&wallet_key[2 + 2 * i..4 + 2 * i], 16).unwrap()).collect(); 
+        eth_keystore::encrypt_key(&directory, &mut rand::thread_rng(), bytes, "password", Some("wallet.json")).unwrap(); 
+        let toml = directory.join("credentials.toml"); 
+        std::fs::write(&toml, format!(
+            "signing_key = \"{}\"\napi_key = \"key\"\napi_secret = \"secret\"\nwallet_keystore = \"{}\"\nwallet_keystore_password = \"password\"\n",
+            signing_key, directory.join("wallet.json").display()
+        )).unwrap(); 
+        let credentials = ClientCredentials::from_file(&toml).unwrap(); 
+        assert_eq!(credentials.wallet_private_key.as_ref().unwrap().expose(), wallet_key); 
+        let signer: PrivateKeySigner = wallet_key.parse().unwrap(); 
+        assert_eq!(credentials.wallet_address, signer.address().to_string()); 
+
+        // Partial credentials are an error
+        std::fs::write(&dotenv, "API_KEY=key\n").unwrap(); 
+        assert!(ClientCredentials::from_file(&dotenv).is_err()); 
+
+        std::fs::remove_dir_all(&directory).unwrap(); 
+    }
+
+    #[test]
+    fn test_secret_redaction() {
+        use credentials::Secret;
+        use ws_structs::{WsRequest, WsRequestData};
+
+        let credentials = ClientCredentials {
+            signing_key : Secret::from("0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"), 
+            wallet_address : "0x1".to_string(), 
+            wallet_private_key : Some(Secret::from("0x8da4ef21b864d2cc526dbdb2a120bd2874c36c9d0a1fb7f8c63d7f7a8b41de8f")), 
+            api_key : "key".to_string(), 
+            api_secret : Secret::from("s3cr3t")
+        }; 
+        let leaks = |text: &str| text.contains("4c0883a6") || text.contains("8da4ef21") || text.contains("s3cr3t"); 
+
+        assert!(!leaks(&format!("{:?}", credentials))); 
+        assert!(!leaks(&format!("{:#?}", credentials))); 
+        assert_eq!(credentials.api_secret.to_string(), "<redacted>"); 
+        assert_eq!(credentials.api_secret.expose(), "s3cr3t"); 
+
+        // The auth request still carries the secret on the wire
+        let auth = WsRequest {
+            op : "auth".to_string(), 
+            data : WsRequestData::AuthData { key : credentials.api_key.clone(), secret : credentials.api_secret.clone() }, 
+            id : Some(1)
+        }; 
+        assert!(!leaks(&format!("{:?}", auth))); 
+        assert!(serde_json::to_string(&auth).unwrap().contains(r#""secret":"s3cr3t""#)); 
+
+        let headers = credentials.auth_headers().unwrap(); 
+        assert!(!leaks(&format!("{:?}", headers))); 
+        assert_eq!(headers["AEVO-SECRET"], "s3cr3t"); 
+
+        let request = reqwest::Client::new().get("https://api.aevo.xyz/account").headers(headers).build().unwrap(); 
+        assert!(!leaks(&format!("{:?}", request))); 
+    }
+
+    #[test(tokio::test)]
+    async fn test_register_signatures() {
+        use alloy::{primitives::{B256, Signature}, signers::local::PrivateKeySigner, sol_types::{Eip712Domain, SolStruct}};
+        use registration::{signer_key, RegisterData};
+        use signature::{sign_register, Register, SignKey};
+
+        let wallet = PrivateKeySigner::random(); 
+        let signing_key = PrivateKeySigner::random(); 
+        let (account_signature, signing_key_signature) = sign_register(&env::ENV::TESTNET, &wallet, &signing_key, 1735689600).await.unwrap(); 
+
+        let config = env::ENV::TESTNET.get_config().signing_domain; 
+        let domain = Eip712Domain {
+            name : Some(config.name.into()), 
+            version : Some(config.version.into()), 
+            chain_id : Some(config.chain_id), 
+            verifying_contract : None, 
+            salt : None
+        }; 
+        let recover = |signature: &str, hash: B256| {
+            Signature::try_from(alloy::hex::decode(signature).unwrap().as_slice()).unwrap().recover_address_from_prehash(&hash).unwrap()
+        }; 
+
+        // The wallet vouches for the key and expiry, the key for the wallet
+        let register = Register { key : signing_key.address(), expiry : alloy::primitives::U256::from(1735689600_u64) }; 
+        assert_eq!(recover(&account_signature, register.eip712_signing_hash(&domain)), wallet.address()); 
+        let sign_key = SignKey { account : wallet.address() }; 
+        assert_eq!(recover(&signing_key_signature, sign_key.eip712_signing_hash(&domain)), signing_key.address()); 
+
+        // A generated key reads back as the same signer
+        let restored: PrivateKeySigner = signer_key(&signing_key).expose().parse().unwrap(); 
+        assert_eq!(restored.address(), signing_key.address()); 
+
+        let data = serde_json::from_str::<RegisterData>(
+            r#"{"api_key":"key","api_secret":"s3cr3t","signing_keys":[{"signing_key":"0x2","expiry":"1735689600000000000","created_timestamp":"1722988800000000000"}]}"#
+        ).unwrap(); 
+        assert_eq!(data.api_secret.expose(), "s3cr3t"); 
+        assert_eq!(data.signing_keys[0].expiry.timestamp(), 1735689600); 
+        assert!(!format!("{:?}", data).contains("s3cr3t")); 
+    }
+
+    #[test]
+    fn test_key_expiry_status() {
+        use chrono::{Duration, TimeZone, Utc};
+        use key_rotation::{key_status, KeyStatus};
+        use rest::SigningKeyInfo;
+
+        let now = Utc.with_ymd_and_hms(2024, 8, 7, 0, 0, 0).unwrap(); 
+        let key = |address: &str, expiry| SigningKeyInfo { signing_key : address.to_string(), expiry, created_timestamp : now - Duration::days(30) }; 
+        let keys = vec![
+            key("0xAbC1", now + Duration::days(30)), 
+            key("0xabc2", now + Duration::days(3)), 
+            key("0xabc3", now - Duration::hours(1))
+        ]; 
+        let warn_before = Duration::days(7); 
+
+        // Addresses match regardless of checksum casing
+        assert_eq!(key_status(&keys, "0xabc1", now, warn_before), KeyStatus::Valid(now + Duration::days(30))); 
+        assert_eq!(key_status(&keys, "0xABC2", now, warn_before), KeyStatus::Expiring(now + Duration::days(3))); 
+        assert_eq!(key_status(&keys, "0xabc3", now, warn_before), KeyStatus::Expired(now - Duration::hours(1))); 
+        assert_eq!(key_status(&keys, "0xabc4", now, warn_before), KeyStatus::Unknown); 
+        assert_eq!(KeyStatus::Unknown.expiry(), None); 
+    }
+
+    #[test]
+    fn test_order_reconcile() {
+        use chrono::{Duration, Utc};
+        use order_manager::{OrderManager, OrderState, Submission};
+
+        let order = |order_id: &str, status: &str, filled: &str| serde_json::from_str::<rest::OrderData>(&format!(
+            r#"{{"order_id":"{order_id}","account":"0x1","instrument_id":"1","instrument_name":"ETH-PERP","instrument_type":"PERPETUAL","order_type":"limit","order_status":"{status}","side":"buy","amount":"0.02","price":"2400","filled":"{filled}","initial_margin":"5","created_timestamp":"1700000000000000000","timestamp":"1700000000000000000","system_type":"API"}}"#
+        )).unwrap(); 
+
+        let mut manager = OrderManager::new(); 
+        manager.record_submission(Submission { order_id : "0xc".to_string(), instrument_id : 1, is_buy : true, price : Some(types::Decimal::from(2400)), amount : "0.02".parse().unwrap(), request_id : Some(1), replaces : None }); 
+        manager.record_submission(Submission { order_id : "0xd".to_string(), instrument_id : 1, is_buy : true, price : Some(types::Decimal::from(2400)), amount : "0.02".parse().unwrap(), request_id : Some(2), replaces : None }); 
+        manager.apply_order_data(&order("0xd", "opened", "0")); 
+
+        // The fresh pending order may not be listed yet, the acknowledged one needs a lookup
+        let now = Utc::now(); 
+        assert_eq!(manager.reconcile(&[], now, Duration::seconds(10)), vec!["0xd".to_string()]); 
+        assert_eq!(manager.get("0xd").unwrap().state, OrderState::Acknowledged); 
+        let mut missing = manager.reconcile(&[], now + Duration::minutes(1), Duration::seconds(10)); 
+        missing.sort(); 
+        assert_eq!(missing, vec!["0xc".to_string(), "0xd".to_string()]); 
+
+        // It filled while disconnected, and the pending one never arrived
+        manager.apply_order_data(&order("0xd", "filled", "0.02")); 
+        manager.mark_unknown("0xc"); 
+        assert_eq!(manager.get("0xd").unwrap().state, OrderState::Filled); 
+        assert_eq!(manager.get("0xc").unwrap().state, OrderState::Rejected); 
+    }
 }
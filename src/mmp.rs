@@ -0,0 +1,181 @@
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::{Duration, Instant}};
+use chrono::Utc;
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use tokio::{sync::mpsc::{self, UnboundedReceiver, UnboundedSender}, task::JoinHandle};
+use tokio_tungstenite::tungstenite::Message;
+use crate::{
+    aevo::AevoClient,
+    rest::{MmpData, RestResponse},
+    types::Timestamp,
+    ws_structs::{MmpEvent, WsRequest, WsRequestData, WsResponse, WsResponseData}
+};
+
+const RESET_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmpState {
+    pub asset : String,
+    pub frozen : bool,
+    pub frozen_until : Option<Timestamp>,
+    pub last_triggered : Option<Timestamp>,
+    pub triggers : u64
+}
+
+impl MmpState {
+    fn new(asset: &str) -> MmpState {
+        MmpState { asset : asset.to_string(), frozen : false, frozen_until : None, last_triggered : None, triggers : 0 }
+    }
+
+    // The exchange lifts a timed freeze at frozen_until without necessarily sending an event
+    pub fn is_frozen(&self, now: Timestamp) -> bool {
+        self.frozen && self.frozen_until.is_none_or(|until| until > now)
+    }
+}
+
+// Freeze state per asset from the mmp channel and the REST api
+#[derive(Debug, Default)]
+pub struct MmpTracker {
+    states : HashMap<String, MmpState>,
+    subscribers : Vec<UnboundedSender<MmpEvent>>
+}
+
+impl MmpTracker {
+    pub fn new() -> MmpTracker {
+        MmpTracker::default()
+    }
+
+    pub fn subscribe(&mut self) -> UnboundedReceiver<MmpEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub fn apply_event(&mut self, event: &MmpEvent) {
+        let state = self.states.entry(event.asset.clone()).or_insert_with(|| MmpState::new(&event.asset));
+        if event.frozen && !state.is_frozen(Utc::now()) {
+            warn!("MMP triggered on {}, frozen until {:?}", event.asset, event.frozen_end_time);
+            state.triggers += 1;
+            state.last_triggered = Some(Utc::now());
+        }
+        state.frozen = event.frozen;
+        state.frozen_until = event.frozen_end_time;
+
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    pub fn apply_mmp_data(&mut self, data: &[MmpData]) {
+        let now = Utc::now();
+        for mmp in data {
+            let state = self.states.entry(mmp.asset.clone()).or_insert_with(|| MmpState::new(&mmp.asset));
+            state.frozen = mmp.is_frozen(now);
+            state.frozen_until = mmp.frozen_end_time;
+        }
+    }
+
+    pub fn mark_reset(&mut self, asset: &str) {
+        let state = self.states.entry(asset.to_string()).or_insert_with(|| MmpState::new(asset));
+        state.frozen = false;
+        state.frozen_until = None;
+    }
+
+    // Feeds a message from read_messages into the tracker
+    pub fn apply(&mut self, response: &WsResponse) {
+        if let WsResponse::SubscribeResponse { data : WsResponseData::MmpData(event), .. } = response {
+            self.apply_event(event);
+        }
+    }
+
+    pub fn get(&self, asset: &str) -> Option<&MmpState> {
+        self.states.get(asset)
+    }
+
+    pub fn is_frozen(&self, asset: &str) -> bool {
+        self.states.get(asset).is_some_and(|s| s.is_frozen(Utc::now()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MmpResetPolicy {
+    // Wait after a trigger before resetting, so the strategy has time to react
+    pub cooldown : Duration,
+    // Assets reset automatically, None for every asset
+    pub assets : Option<Vec<String>>,
+    // Stops resetting an asset that keeps triggering, None for no limit
+    pub max_resets_per_hour : Option<usize>
+}
+
+impl Default for MmpResetPolicy {
+    fn default() -> MmpResetPolicy {
+        MmpResetPolicy { cooldown : Duration::from_secs(30), assets : None, max_resets_per_hour : Some(5) }
+    }
+}
+
+impl MmpResetPolicy {
+    fn applies_to(&self, asset: &str) -> bool {
+        match &self.assets {
+            Some(assets) => assets.iter().any(|a| a == asset),
+            None => true
+        }
+    }
+}
+
+impl AevoClient {
+    pub async fn subscribe_mmp(&self) -> Result<()> {
+        let request = WsRequest {
+            op : "subscribe".to_string(),
+            data : WsRequestData::ChannelData(vec!["mmp".to_string()]),
+            id: None
+        };
+
+        let msg = Message::from(serde_json::to_string(&request)?);
+        self.send(&msg).await
+    }
+
+    // Loads the MMP state of every asset into the tracker
+    pub async fn load_mmp(&self) -> Result<Vec<MmpData>> {
+        match self.rest_get_mmp().await? {
+            RestResponse::GetMmp(data) => {
+                self.mmp.lock().await.apply_mmp_data(&data);
+                Ok(data)
+            },
+            response => Err(eyre!("Unexpected mmp response: {:?}", response))
+        }
+    }
+
+    // Resets MMP after the cooldown each time it triggers on an asset covered by the policy.
+    // Requires subscribe_mmp and a running read_messages.
+    pub async fn spawn_mmp_auto_reset(client: Arc<AevoClient>, policy: MmpResetPolicy) -> JoinHandle<()> {
+        let mut events = client.mmp.lock().await.subscribe();
+
+        tokio::spawn(async move {
+            let mut resets: HashMap<String, VecDeque<Instant>> = HashMap::new();
+
+            while let Some(event) = events.recv().await {
+                if !event.frozen || !policy.applies_to(&event.asset) {
+                    continue;
+                }
+
+                let history = resets.entry(event.asset.clone()).or_default();
+                while history.front().is_some_and(|t| t.elapsed() > RESET_WINDOW) {
+                    history.pop_front();
+                }
+                if policy.max_resets_per_hour.is_some_and(|limit| history.len() >= limit) {
+                    warn!("MMP on {} triggered {} times in the last hour, not resetting", event.asset, history.len());
+                    continue;
+                }
+
+                tokio::time::sleep(policy.cooldown).await;
+                if !client.mmp.lock().await.is_frozen(&event.asset) {
+                    continue;
+                }
+
+                info!("Resetting MMP on {} after {:?} cooldown", event.asset, policy.cooldown);
+                match client.rest_reset_mmp(event.asset.clone()).await {
+                    Ok(_) => history.push_back(Instant::now()),
+                    Err(e) => error!("Problem resetting MMP on {}: {}", event.asset, e)
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use chrono::NaiveDate;
+use log::{info, debug};
+use eyre::{eyre, Result};
+use crate::{
+    aevo::AevoClient,
+    instruments::{InstrumentName, OptionKind},
+    rest::{MarketInfo, RestResponse},
+    types::Decimal,
+    ws_structs::{Ticker, WsResponse, WsResponseData}
+};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OptionQuote {
+    pub instrument_id : u64,
+    pub instrument_name : String,
+    pub mark_price : Option<Decimal>,
+    pub bid_price : Option<Decimal>,
+    pub ask_price : Option<Decimal>,
+    pub iv : Option<Decimal>,
+    pub bid_iv : Option<Decimal>,
+    pub ask_iv : Option<Decimal>,
+    pub delta : Option<Decimal>,
+    pub gamma : Option<Decimal>,
+    pub theta : Option<Decimal>,
+    pub vega : Option<Decimal>,
+    pub rho : Option<Decimal>
+}
+
+impl OptionQuote {
+    fn apply_ticker(&mut self, ticker: &Ticker) {
+        self.mark_price = Some(ticker.mark.price);
+        self.iv = ticker.mark.iv.or(self.iv);
+        self.delta = ticker.mark.delta.or(self.delta);
+        self.gamma = ticker.mark.gamma.or(self.gamma);
+        self.theta = ticker.mark.theta.or(self.theta);
+        self.vega = ticker.mark.vega.or(self.vega);
+        self.rho = ticker.mark.rho.or(self.rho);
+        self.bid_price = non_zero(ticker.bid.price);
+        self.ask_price = non_zero(ticker.ask.price);
+        self.bid_iv = ticker.bid.iv;
+        self.ask_iv = ticker.ask.iv;
+    }
+}
+
+// The exchange reports an empty side of the book as a zero price
+fn non_zero(price: Decimal) -> Option<Decimal> {
+    if price.is_zero() { None } else { Some(price) }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StrikeRow {
+    pub call : Option<OptionQuote>,
+    pub put : Option<OptionQuote>
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExpirySlice {
+    pub strikes : BTreeMap<Decimal, StrikeRow>,
+    // Forward published by the exchange in the markets endpoint
+    pub forward_price : Option<Decimal>
+}
+
+impl ExpirySlice {
+    fn quote_mut(&mut self, strike: Decimal, option_kind: OptionKind) -> &mut Option<OptionQuote> {
+        let row = self.strikes.entry(strike).or_default();
+        match option_kind {
+            OptionKind::Call => &mut row.call,
+            OptionKind::Put => &mut row.put
+        }
+    }
+
+    // Put-call parity at the strike where call and put marks are closest. Aevo options are
+    // cash settled against a zero rate so no discounting is applied.
+    pub fn implied_forward(&self) -> Option<Decimal> {
+        self.strikes.iter()
+            .filter_map(|(strike, row)| {
+                let call = row.call.as_ref()?.mark_price?;
+                let put = row.put.as_ref()?.mark_price?;
+                Some((strike, call - put))
+            })
+            .min_by_key(|(_, diff)| diff.abs())
+            .map(|(strike, diff)| strike + diff)
+            .or(self.forward_price)
+    }
+
+    pub fn atm_strike(&self) -> Option<Decimal> {
+        let forward = self.implied_forward()?;
+        self.strikes.keys().min_by_key(|strike| (**strike - forward).abs()).copied()
+    }
+
+    pub fn atm_iv(&self) -> Option<Decimal> {
+        let row = self.strikes.get(&self.atm_strike()?)?;
+        let ivs: Vec<Decimal> = [&row.call, &row.put].iter()
+            .filter_map(|quote| quote.as_ref().and_then(|q| q.iv))
+            .collect();
+
+        if ivs.is_empty() {
+            return None
+        }
+        Some(ivs.iter().sum::<Decimal>() / Decimal::from(ivs.len()))
+    }
+
+    // Risk reversal: iv of the call closest to `delta` minus iv of the put closest to `-delta`
+    pub fn skew(&self, delta: Decimal) -> Option<Decimal> {
+        let closest = |quotes: Vec<&OptionQuote>, target: Decimal| {
+            quotes.into_iter()
+                .filter(|q| q.delta.is_some() && q.iv.is_some())
+                .min_by_key(|q| (q.delta.unwrap_or_default() - target).abs())
+                .and_then(|q| q.iv)
+        };
+
+        let calls = self.strikes.values().filter_map(|row| row.call.as_ref()).collect();
+        let puts = self.strikes.values().filter_map(|row| row.put.as_ref()).collect();
+
+        Some(closest(calls, delta)? - closest(puts, -delta)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OptionChain {
+    pub asset : String,
+    pub expiries : BTreeMap<NaiveDate, ExpirySlice>
+}
+
+impl OptionChain {
+    pub fn new(asset: &str) -> OptionChain {
+        OptionChain { asset : asset.to_string(), expiries : BTreeMap::new() }
+    }
+
+    pub fn from_markets(asset: &str, markets: &[MarketInfo]) -> OptionChain {
+        let mut chain = OptionChain::new(asset);
+        for market in markets {
+            chain.insert_market(market);
+        }
+        chain
+    }
+
+    pub fn insert_market(&mut self, market: &MarketInfo) {
+        if let MarketInfo::Option { instrument_id, instrument_name, underlying_asset, mark_price, forward_price, greeks, .. } = market {
+            if underlying_asset != &self.asset {
+                return
+            }
+
+            let (expiry, strike, option_kind) = match option_key(instrument_name) {
+                Some(key) => key,
+                None => return
+            };
+
+            let slice = self.expiries.entry(expiry).or_default();
+            slice.forward_price = Some(*forward_price);
+            *slice.quote_mut(strike, option_kind) = Some(OptionQuote {
+                instrument_id : *instrument_id,
+                instrument_name : instrument_name.clone(),
+                mark_price : Some(*mark_price),
+                iv : Some(greeks.iv),
+                delta : Some(greeks.delta),
+                gamma : Some(greeks.gamma),
+                theta : Some(greeks.theta),
+                vega : Some(greeks.vega),
+                rho : Some(greeks.rho),
+                ..OptionQuote::default()
+            });
+        }
+    }
+
+    pub fn apply_ticker(&mut self, ticker: &Ticker) {
+        let (expiry, strike, option_kind) = match option_key(&ticker.instrument_name) {
+            Some(key) => key,
+            None => return
+        };
+
+        let quote = self.expiries.entry(expiry).or_default()
+            .quote_mut(strike, option_kind)
+            .get_or_insert_with(|| OptionQuote {
+                instrument_id : ticker.instrument_id,
+                instrument_name : ticker.instrument_name.clone(),
+                ..OptionQuote::default()
+            });
+        quote.apply_ticker(ticker);
+    }
+
+    // Feeds a message from read_messages into the chain. Returns true if the chain changed.
+    pub fn apply(&mut self, response: &WsResponse) -> bool {
+        match response {
+            WsResponse::SubscribeResponse { channel, data : WsResponseData::TickerData { tickers, .. }, .. }
+                if channel == &format!("ticker:{}:OPTION", self.asset) => {
+                for ticker in tickers {
+                    self.apply_ticker(ticker);
+                }
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn expiry(&self, expiry: NaiveDate) -> Option<&ExpirySlice> {
+        self.expiries.get(&expiry)
+    }
+
+    pub fn row(&self, expiry: NaiveDate, strike: Decimal) -> Option<&StrikeRow> {
+        self.expiries.get(&expiry)?.strikes.get(&strike)
+    }
+
+    pub fn implied_forward(&self, expiry: NaiveDate) -> Option<Decimal> {
+        self.expiries.get(&expiry)?.implied_forward()
+    }
+
+    pub fn atm_strike(&self, expiry: NaiveDate) -> Option<Decimal> {
+        self.expiries.get(&expiry)?.atm_strike()
+    }
+
+    pub fn skew(&self, expiry: NaiveDate, delta: Decimal) -> Option<Decimal> {
+        self.expiries.get(&expiry)?.skew(delta)
+    }
+
+    // ATM implied volatility for every expiry, nearest first
+    pub fn term_structure(&self) -> Vec<(NaiveDate, Decimal)> {
+        self.expiries.iter()
+            .filter_map(|(expiry, slice)| Some((*expiry, slice.atm_iv()?)))
+            .collect()
+    }
+}
+
+fn option_key(instrument_name: &str) -> Option<(NaiveDate, Decimal, OptionKind)> {
+    match instrument_name.parse::<InstrumentName>() {
+        Ok(name) => Some((name.expiry()?, name.strike()?, name.option_kind()?)),
+        Err(e) => {
+            debug!("Skipping instrument {}: {}", instrument_name, e);
+            None
+        }
+    }
+}
+
+impl AevoClient {
+    pub async fn load_option_chain(&self, asset: String) -> Result<OptionChain> {
+        info!("Loading {} option chain", asset);
+        match self.get_markets(asset.clone()).await? {
+            RestResponse::GetMarkets(markets) => Ok(OptionChain::from_markets(&asset, &markets)),
+            response => Err(eyre!("Unexpected markets response: {:?}", response))
+        }
+    }
+}
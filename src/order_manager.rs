@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet};
+use chrono::{Duration, Utc};
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use crate::{
+    aevo::AevoClient,
+    rest::{OrderData, RestResponse},
+    types::{Decimal, Timestamp},
+    ws_structs::{Fill, Order, WsResponse, WsResponseData}
+};
+
+// Orders submitted this recently are not looked up when the REST api does not list them yet
+pub const RECONCILE_GRACE_SECS: i64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OrderState {
+    // Sent to the exchange, no reply yet
+    Pending,
+    Acknowledged,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Rejected
+}
+
+impl OrderState {
+    pub fn is_open(&self) -> bool {
+        matches!(self, OrderState::Pending | OrderState::Acknowledged | OrderState::PartiallyFilled)
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        !self.is_open()
+    }
+
+    // Maps the exchange order_status onto a local state
+    pub fn from_status(order_status: &str, filled: Decimal) -> OrderState {
+        match order_status {
+            "filled" => OrderState::Filled,
+            "cancelled" | "canceled" | "expired" => OrderState::Cancelled,
+            "rejected" => OrderState::Rejected,
+            "partial" | "partially_filled" => OrderState::PartiallyFilled,
+            _ if filled > Decimal::ZERO => OrderState::PartiallyFilled,
+            _ => OrderState::Acknowledged
+        }
+    }
+}
+
+// An order as it is sent, tracked as Pending until the exchange replies
+#[derive(Debug, Clone, PartialEq)]
+pub struct Submission {
+    pub order_id : String,
+    pub instrument_id : u64,
+    pub is_buy : bool,
+    // None for market orders
+    pub price : Option<Decimal>,
+    pub amount : Decimal,
+    pub request_id : Option<u64>,
+    // The order an edit replaces
+    pub replaces : Option<String>
+}
+
+// An order as the REST api, the orders channel or an order reply report it
+struct ReportedOrder<'a> {
+    order_id : &'a str,
+    instrument_id : u64,
+    instrument_name : &'a str,
+    side : &'a str,
+    price : Decimal,
+    amount : Decimal,
+    filled : Decimal,
+    avg_price : Option<Decimal>,
+    order_status : &'a str
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedOrder {
+    pub order_id : String,
+    pub instrument_id : u64,
+    pub instrument_name : Option<String>,
+    pub is_buy : bool,
+    // None for market orders
+    pub price : Option<Decimal>,
+    pub amount : Decimal,
+    pub filled : Decimal,
+    pub avg_price : Option<Decimal>,
+    pub state : OrderState,
+    // Request id sent with the websocket op, used to match error replies
+    pub request_id : Option<u64>,
+    // Order this one replaced through edit_order
+    pub replaces : Option<String>,
+    pub reject_reason : Option<String>,
+    pub created : Timestamp,
+    pub updated : Timestamp
+}
+
+impl TrackedOrder {
+    pub fn remaining(&self) -> Decimal {
+        (self.amount - self.filled).max(Decimal::ZERO)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderEvent {
+    pub previous : Option<OrderState>,
+    pub order : TrackedOrder
+}
+
+#[derive(Debug, Default)]
+pub struct OrderManager {
+    orders : HashMap<String, TrackedOrder>,
+    // Fill amount per order summed from the fills channel, keyed by trade id to drop duplicates
+    fills : HashMap<String, (HashSet<String>, Decimal)>,
+    subscribers : Vec<UnboundedSender<OrderEvent>>
+}
+
+impl OrderManager {
+    pub fn new() -> OrderManager {
+        OrderManager::default()
+    }
+
+    pub fn subscribe(&mut self) -> UnboundedReceiver<OrderEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    fn notify(&mut self, previous: Option<OrderState>, order_id: &str) {
+        if let Some(order) = self.orders.get(order_id) {
+            let event = OrderEvent { previous, order : order.clone() };
+            self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
+
+    pub fn record_submission(&mut self, submission: Submission) {
+        let now = Utc::now();
+        let order_id = submission.order_id.clone();
+        self.orders.insert(order_id.clone(), TrackedOrder {
+            order_id : submission.order_id,
+            instrument_id : submission.instrument_id,
+            instrument_name : None,
+            is_buy : submission.is_buy,
+            price : submission.price,
+            amount : submission.amount,
+            filled : Decimal::ZERO,
+            avg_price : None,
+            state : OrderState::Pending,
+            request_id : submission.request_id,
+            replaces : submission.replaces,
+            reject_reason : None,
+            created : now,
+            updated : now
+        });
+        self.notify(None, &order_id);
+    }
+
+    fn transition(&mut self, order_id: &str, state: OrderState) {
+        let previous = match self.orders.get_mut(order_id) {
+            Some(order) => {
+                let previous = order.state;
+                // Late or duplicated events must not reopen a finished order
+                if previous == state || (previous.is_terminal() && state.is_open()) {
+                    return
+                }
+                order.state = state;
+                order.updated = Utc::now();
+                previous
+            },
+            None => return
+        };
+        self.notify(Some(previous), order_id);
+    }
+
+    pub fn mark_rejected(&mut self, order_id: &str, reason: &str) {
+        if let Some(order) = self.orders.get_mut(order_id) {
+            order.reject_reason = Some(reason.to_string());
+        }
+        self.transition(order_id, OrderState::Rejected);
+    }
+
+    pub fn mark_cancelled(&mut self, order_id: &str) {
+        self.transition(order_id, OrderState::Cancelled);
+    }
+
+    // Upserts an order reported by the exchange, both from the REST api and the orders channel
+    fn upsert(&mut self, reported: ReportedOrder<'_>) {
+        let ReportedOrder { order_id, instrument_id, instrument_name, side, price, amount, filled, avg_price, order_status } = reported;
+        let now = Utc::now();
+        let previous = self.orders.get(order_id).map(|o| o.state);
+        let fill_total = self.fills.get(order_id).map(|(_, total)| *total).unwrap_or_default();
+
+        let order = self.orders.entry(order_id.to_string()).or_insert_with(|| TrackedOrder {
+            order_id : order_id.to_string(),
+            instrument_id,
+            instrument_name : None,
+            is_buy : side == "buy",
+            price : Some(price),
+            amount,
+            filled : Decimal::ZERO,
+            avg_price : None,
+            state : OrderState::Acknowledged,
+            request_id : None,
+            replaces : None,
+            reject_reason : None,
+            created : now,
+            updated : now
+        });
+
+        order.instrument_name = Some(instrument_name.to_string());
+        order.price = Some(price);
+        order.amount = amount;
+        order.filled = filled.max(fill_total).max(order.filled);
+        order.avg_price = avg_price.or(order.avg_price);
+        order.updated = now;
+
+        let state = OrderState::from_status(order_status, order.filled);
+        if !(order.state.is_terminal() && state.is_open()) {
+            order.state = state;
+        }
+
+        // The first reply for an edited order confirms the order it replaced is gone
+        let replaces = match previous {
+            None | Some(OrderState::Pending) if order.state != OrderState::Rejected => order.replaces.clone(),
+            _ => None
+        };
+
+        self.notify(previous, order_id);
+
+        if let Some(replaced) = replaces {
+            self.mark_cancelled(&replaced);
+        }
+    }
+
+    pub fn apply_order_data(&mut self, order: &OrderData) {
+        self.upsert(ReportedOrder {
+            order_id : &order.order_id,
+            instrument_id : order.instrument_id,
+            instrument_name : &order.instrument_name,
+            side : &order.side,
+            price : order.price,
+            amount : order.amount,
+            filled : order.filled,
+            avg_price : order.avg_price,
+            order_status : &order.order_status
+        });
+    }
+
+    pub fn apply_order(&mut self, order: &Order) {
+        self.upsert(ReportedOrder {
+            order_id : &order.order_id,
+            instrument_id : order.instrument_id,
+            instrument_name : &order.instrument_name,
+            side : &order.side,
+            price : order.price,
+            amount : order.amount,
+            filled : order.filled,
+            avg_price : None,
+            order_status : &order.order_status
+        });
+    }
+
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        let (trade_ids, total) = self.fills.entry(fill.order_id.clone()).or_default();
+        if !trade_ids.insert(fill.trade_id.clone()) {
+            return
+        }
+        *total += fill.filled;
+        let fill_total = *total;
+
+        let previous = match self.orders.get_mut(&fill.order_id) {
+            Some(order) => {
+                let previous = order.state;
+                let filled_before = order.filled;
+                order.filled = order.filled.max(fill_total);
+                order.avg_price = match order.avg_price {
+                    Some(avg) if !order.filled.is_zero() => {
+                        Some((avg * filled_before + fill.price * (order.filled - filled_before)) / order.filled)
+                    },
+                    _ => Some(fill.price)
+                };
+                order.updated = Utc::now();
+
+                let state = OrderState::from_status(&fill.order_status, order.filled);
+                if !(previous.is_terminal() && state.is_open()) {
+                    order.state = state;
+                }
+                previous
+            },
+            None => return
+        };
+
+        self.notify(Some(previous), &fill.order_id);
+    }
+
+    // Feeds a message from read_messages into the manager
+    pub fn apply(&mut self, response: &WsResponse) {
+        match response {
+            WsResponse::SubscribeResponse { data, .. } | WsResponse::PublishResponse { data, .. } => {
+                match data {
+                    WsResponseData::CreateEditOrderData {
+                        order_id, instrument_id, instrument_name, side, price, amount, filled, avg_price, order_status, ..
+                    } => self.upsert(ReportedOrder {
+                        order_id,
+                        instrument_id : *instrument_id,
+                        instrument_name,
+                        side,
+                        price : *price,
+                        amount : *amount,
+                        filled : *filled,
+                        avg_price : *avg_price,
+                        order_status
+                    }),
+                    WsResponseData::OrdersData { orders, .. } => {
+                        for order in orders {
+                            self.apply_order(order);
+                        }
+                    },
+                    WsResponseData::FillsData { fill, .. } => self.apply_fill(fill),
+                    WsResponseData::CancelOrderData { success : true, order_id } => self.mark_cancelled(order_id),
+                    WsResponseData::CancelAllOrdersData { success : true, order_ids } => {
+                        for order_id in order_ids {
+                            self.mark_cancelled(order_id);
+                        }
+                    },
+                    _ => {}
+                }
+            },
+            WsResponse::ErrorResponse { id : Some(id), error } => {
+                let rejected: Vec<String> = self.orders.values()
+                    .filter(|o| o.state == OrderState::Pending && o.request_id == Some(*id))
+                    .map(|o| o.order_id.clone())
+                    .collect();
+                for order_id in rejected {
+                    warn!("Order {} rejected: {}", order_id, error);
+                    self.mark_rejected(&order_id, error);
+                }
+            },
+            WsResponse::ErrorResponse { id : None, error } => error!("Aevo websocket error: {}", error)
+        }
+    }
+
+    // Aligns the local view with the open orders reported by the exchange and returns the orders we
+    // consider open that it did not list. Those may have filled or been cancelled while we were
+    // disconnected, so they are looked up one by one. Pending orders younger than grace are left
+    // out since the REST api may not list them yet.
+    pub fn reconcile(&mut self, open_orders: &[OrderData], now: Timestamp, grace: Duration) -> Vec<String> {
+        let open_ids: HashSet<&str> = open_orders.iter().map(|o| o.order_id.as_str()).collect();
+
+        for order in open_orders {
+            self.apply_order_data(order);
+        }
+
+        self.orders.values()
+            .filter(|o| o.state.is_open() && !open_ids.contains(o.order_id.as_str()))
+            .filter(|o| !(o.state == OrderState::Pending && now - o.created < grace))
+            .map(|o| o.order_id.clone())
+            .collect()
+    }
+
+    // An order the exchange does not know. One that was never acknowledged did not arrive.
+    pub fn mark_unknown(&mut self, order_id: &str) {
+        match self.orders.get(order_id).map(|o| o.state) {
+            Some(OrderState::Pending) => self.mark_rejected(order_id, "Unknown to the exchange"),
+            Some(_) => self.mark_cancelled(order_id),
+            None => {}
+        }
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&TrackedOrder> {
+        self.orders.get(order_id)
+    }
+
+    pub fn orders(&self) -> impl Iterator<Item = &TrackedOrder> {
+        self.orders.values()
+    }
+
+    pub fn open_orders(&self) -> Vec<&TrackedOrder> {
+        self.orders.values().filter(|o| o.state.is_open()).collect()
+    }
+
+    pub fn open_orders_for(&self, instrument_id: u64) -> Vec<&TrackedOrder> {
+        self.orders.values().filter(|o| o.state.is_open() && o.instrument_id == instrument_id).collect()
+    }
+
+    // Drops finished orders so long running processes do not grow without bound
+    pub fn prune(&mut self) {
+        self.orders.retain(|_, o| o.state.is_open());
+        let orders = &self.orders;
+        self.fills.retain(|order_id, _| orders.contains_key(order_id));
+    }
+}
+
+impl AevoClient {
+    pub async fn reconcile_orders(&self) -> Result<()> {
+        info!("Reconciling local orders with the exchange");
+        match self.rest_get_open_orders().await? {
+            RestResponse::GetOrders(open_orders) => {
+                let missing = self.orders.lock().await.reconcile(&open_orders, Utc::now(), Duration::seconds(RECONCILE_GRACE_SECS));
+                for order_id in missing {
+                    match self.rest_get_order(&order_id).await {
+                        Ok(Some(order)) => {
+                            info!("Order {} is {} on the exchange", order_id, order.order_status);
+                            self.orders.lock().await.apply_order_data(&order);
+                        },
+                        Ok(None) => {
+                            warn!("Order {} is unknown to the exchange", order_id);
+                            self.orders.lock().await.mark_unknown(&order_id);
+                        },
+                        // Left open, the next reconcile or the orders channel settles it
+                        Err(e) => warn!("Problem looking up order {}: {}", order_id, e)
+                    }
+                }
+                Ok(())
+            },
+            response => Err(eyre!("Unexpected open orders response: {:?}", response))
+        }
+    }
+}
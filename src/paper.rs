@@ -0,0 +1,785 @@
+use std::collections::HashMap;
+use chrono::Utc;
+use log::{info, error};
+use eyre::{eyre, Result};
+use tokio::sync::{mpsc::{self, UnboundedReceiver, UnboundedSender}, Mutex};
+use crate::{
+    aevo::AevoClient,
+    batch::CancelFilter,
+    instruments::to_decimal,
+    order_manager::Submission,
+    rest::{FeeStructureInfo, OrderData},
+    risk::RiskReservation,
+    types::{Decimal, Timestamp},
+    ws_structs::{Fill, Order, Position, WsResponse, WsResponseData}
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperConfig {
+    // Reported as the account of every paper order
+    pub account : String,
+    // Fees as a fraction of the traded notional, used for assets and instrument types missing from
+    // fee_structures
+    pub maker_fee : Decimal,
+    pub taker_fee : Decimal,
+    // Fees of the account, as returned by rest_get_account
+    pub fee_structures : Vec<FeeStructureInfo>
+}
+
+impl Default for PaperConfig {
+    fn default() -> PaperConfig {
+        PaperConfig {
+            account : "paper".to_string(),
+            maker_fee : Decimal::new(3, 4),
+            taker_fee : Decimal::new(5, 4),
+            fee_structures : Vec::new()
+        }
+    }
+}
+
+impl PaperConfig {
+    pub fn fee_rate(&self, asset: &str, instrument_type: &str, maker: bool) -> Decimal {
+        match self.fee_structures.iter().find(|f| f.asset == asset && f.instrument_type == instrument_type) {
+            Some(fees) if maker => fees.maker_fee,
+            Some(fees) => fees.taker_fee,
+            None if maker => self.maker_fee,
+            None => self.taker_fee
+        }
+    }
+}
+
+// An order op as the client takes it, before rounding and risk checks. Edits name the order they
+// replace and market orders have no limit price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct PaperOrderParams<'a> {
+    pub replaces : Option<&'a str>,
+    pub instrument_id : u64,
+    pub is_buy : bool,
+    pub limit_price : Option<f64>,
+    pub quantity : f64,
+    pub post_only : bool,
+    pub ioc : bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaperOrderRequest {
+    pub order_id : String,
+    pub instrument_id : u64,
+    pub instrument_name : String,
+    pub instrument_type : String,
+    pub asset : String,
+    pub is_buy : bool,
+    // None for market orders
+    pub price : Option<Decimal>,
+    pub amount : Decimal,
+    pub post_only : bool,
+    // The unfilled remainder is cancelled instead of resting
+    pub ioc : bool
+}
+
+#[derive(Debug, Clone)]
+struct PaperOrder {
+    request : PaperOrderRequest,
+    filled : Decimal,
+    avg_price : Option<Decimal>,
+    status : String,
+    created : Timestamp,
+    // Size resting at the order's price ahead of it
+    queue_ahead : Decimal
+}
+
+impl PaperOrder {
+    fn remaining(&self) -> Decimal {
+        (self.request.amount - self.filled).max(Decimal::ZERO)
+    }
+
+    fn is_open(&self) -> bool {
+        self.status == "opened" || self.status == "partial"
+    }
+
+    fn side(&self) -> &'static str {
+        if self.request.is_buy { "buy" } else { "sell" }
+    }
+}
+
+// Levels as (price, amount), bids best first and asks best first
+#[derive(Debug, Clone, Default)]
+struct PaperBook {
+    bids : Vec<(Decimal, Decimal)>,
+    asks : Vec<(Decimal, Decimal)>,
+    // Set once the orderbook channel was seen, the book ticker is ignored from then on
+    depth : bool
+}
+
+impl PaperBook {
+    fn set_level(levels: &mut Vec<(Decimal, Decimal)>, price: Decimal, amount: Decimal, descending: bool) {
+        levels.retain(|level| level.0 != price);
+        if amount.is_zero() {
+            return
+        }
+        let index = levels.iter().position(|level| if descending { level.0 < price } else { level.0 > price }).unwrap_or(levels.len());
+        levels.insert(index, (price, amount));
+    }
+
+    fn apply_levels(&mut self, bids: &[Vec<Decimal>], asks: &[Vec<Decimal>], snapshot: bool) {
+        if snapshot {
+            self.bids.clear();
+            self.asks.clear();
+        }
+        for level in bids.iter().filter(|l| l.len() >= 2) {
+            PaperBook::set_level(&mut self.bids, level[0], level[1], true);
+        }
+        for level in asks.iter().filter(|l| l.len() >= 2) {
+            PaperBook::set_level(&mut self.asks, level[0], level[1], false);
+        }
+        self.depth = true;
+    }
+
+    // The ticker does not always carry the size at the touch, the full order is assumed to fit then
+    fn apply_touch(&mut self, bid: (Decimal, Option<Decimal>), ask: (Decimal, Option<Decimal>)) {
+        let touch = |(price, amount): (Decimal, Option<Decimal>)| {
+            if price.is_zero() { vec![] } else { vec![(price, amount.unwrap_or(Decimal::MAX))] }
+        };
+        self.bids = touch(bid);
+        self.asks = touch(ask);
+    }
+
+    // Size resting on the side at the price. None when the book only has the touch and the price
+    // is away from it.
+    fn level_amount(&self, is_buy: bool, price: Decimal) -> Option<Decimal> {
+        let levels = if is_buy { &self.bids } else { &self.asks };
+        match levels.iter().find(|level| level.0 == price) {
+            Some(level) => Some(level.1),
+            None if self.depth => Some(Decimal::ZERO),
+            None => None
+        }
+    }
+
+    // Best price an order on the side would trade against
+    fn best_opposite(&self, is_buy: bool) -> Option<Decimal> {
+        let levels = if is_buy { &self.asks } else { &self.bids };
+        levels.first().map(|level| level.0)
+    }
+
+    fn mid(&self) -> Option<Decimal> {
+        match (self.bids.first(), self.asks.first()) {
+            (Some(bid), Some(ask)) => Some((bid.0 + ask.0) / Decimal::TWO),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PaperPosition {
+    instrument_name : String,
+    instrument_type : String,
+    asset : String,
+    // Negative for shorts
+    size : Decimal,
+    avg_entry_price : Decimal,
+    last_price : Decimal
+}
+
+impl PaperPosition {
+    fn trade(&mut self, signed_amount: Decimal, price: Decimal) {
+        let size = self.size + signed_amount;
+        if self.size.is_zero() || (self.size > Decimal::ZERO) != (size > Decimal::ZERO) {
+            // Opened or flipped, the entry is the trade price
+            self.avg_entry_price = if size.is_zero() { Decimal::ZERO } else { price };
+        } else if (signed_amount > Decimal::ZERO) == (self.size > Decimal::ZERO) {
+            self.avg_entry_price = (self.avg_entry_price * self.size + price * signed_amount) / size;
+        }
+        self.size = size;
+        self.last_price = price;
+    }
+}
+
+fn crosses(is_buy: bool, limit: Option<Decimal>, price: Decimal) -> bool {
+    match limit {
+        Some(limit) if is_buy => price <= limit,
+        Some(limit) => price >= limit,
+        None => true
+    }
+}
+
+fn subscribe_response(channel: &str, data: WsResponseData, now: Timestamp) -> WsResponse {
+    WsResponse::SubscribeResponse { channel : channel.to_string(), write_ts : Some(now), data }
+}
+
+// Matches paper orders against the live book. Orders take liquidity at the book's prices when they
+// cross on arrival and fill at their own price once the book moves through them while resting.
+// Liquidity taken is removed from the local copy of the book until the next update replaces it.
+// Resting orders join the back of their price level and also fill from trades on the trades channel
+// once the size ahead of them has traded. Cancellations at the level move them up the queue.
+#[derive(Debug, Default)]
+pub struct PaperExchange {
+    config : PaperConfig,
+    books : HashMap<u64, PaperBook>,
+    orders : HashMap<String, PaperOrder>,
+    positions : HashMap<u64, PaperPosition>,
+    trades : u64,
+    // Simulated time, the wall clock when not set
+    clock : Option<Timestamp>
+}
+
+impl PaperExchange {
+    pub fn new(config: PaperConfig) -> PaperExchange {
+        PaperExchange { config, ..Default::default() }
+    }
+
+    // Stamps messages with simulated instead of wall clock time, for replays
+    pub fn set_clock(&mut self, now: Timestamp) {
+        self.clock = Some(now);
+    }
+
+    pub fn now(&self) -> Timestamp {
+        self.clock.unwrap_or_else(Utc::now)
+    }
+
+    pub fn mid(&self, instrument_id: u64) -> Option<Decimal> {
+        self.books.get(&instrument_id).and_then(|book| book.mid())
+    }
+
+    // Updates the books from the orderbook and book ticker channels and fills the resting orders the
+    // market moved through or traded with. Returns the synthetic messages of the fills.
+    pub fn apply_market(&mut self, response: &WsResponse) -> Vec<WsResponse> {
+        let mut updated = Vec::new();
+        let mut trade = None;
+        if let WsResponse::SubscribeResponse { data, .. } = response {
+            match data {
+                WsResponseData::OrderBookData { r#type, instrument_id, bids, asks, .. } => {
+                    self.books.entry(*instrument_id).or_default().apply_levels(bids, asks, r#type == "snapshot");
+                    updated.push(*instrument_id);
+                },
+                WsResponseData::BookTickerData { tickers, .. } => {
+                    for ticker in tickers {
+                        let book = self.books.entry(ticker.instrument_id).or_default();
+                        if !book.depth {
+                            book.apply_touch((ticker.bid.price, ticker.bid.amount), (ticker.ask.price, ticker.ask.amount));
+                            updated.push(ticker.instrument_id);
+                        }
+                    }
+                },
+                WsResponseData::TradesData { instrument_id, side, price, amount : Some(amount), .. } => {
+                    trade = Some((*instrument_id, side == "buy", *price, *amount));
+                },
+                _ => {}
+            }
+        }
+        self.update_queues(&updated);
+
+        let mut resting: Vec<&PaperOrder> = self.orders.values()
+            .filter(|order| order.is_open() && updated.contains(&order.request.instrument_id))
+            .collect();
+        resting.sort_by_key(|order| order.created);
+        let resting: Vec<String> = resting.into_iter().map(|order| order.request.order_id.clone()).collect();
+
+        let mut messages = Vec::new();
+        for order_id in resting {
+            let fills = self.match_order(&order_id, true);
+            if !fills.is_empty() {
+                messages.extend(self.order_update(&order_id));
+                messages.extend(fills);
+            }
+        }
+        if let Some((instrument_id, taker_buys, price, amount)) = trade {
+            messages.extend(self.match_trade(instrument_id, taker_buys, price, amount));
+        }
+        if !messages.is_empty() {
+            messages.push(self.positions_update());
+        }
+        messages
+    }
+
+    // Accepts an order, filling what crosses the book straight away. Post-only orders that would
+    // cross are rejected and market orders need a book to trade against.
+    pub fn submit(&mut self, request: PaperOrderRequest) -> Result<Vec<WsResponse>> {
+        self.validate(&request)?;
+
+        let order_id = request.order_id.clone();
+        let ioc = request.ioc || request.price.is_none();
+        let now = self.now();
+        info!("Paper order {} {} {} at {:?}", order_id, if request.is_buy { "buys" } else { "sells" }, request.amount, request.price);
+        self.orders.insert(order_id.clone(), PaperOrder {
+            request,
+            filled : Decimal::ZERO,
+            avg_price : None,
+            status : "opened".to_string(),
+            created : now,
+            queue_ahead : Decimal::ZERO
+        });
+
+        let fills = self.match_order(&order_id, false);
+        let books = &self.books;
+        if let Some(order) = self.orders.get_mut(&order_id) {
+            if ioc && order.is_open() {
+                order.status = "cancelled".to_string();
+            } else if let Some(price) = order.request.price {
+                order.queue_ahead = books.get(&order.request.instrument_id)
+                    .and_then(|book| book.level_amount(order.request.is_buy, price))
+                    .unwrap_or_default();
+            }
+        }
+
+        let mut messages: Vec<WsResponse> = self.order_update(&order_id).into_iter().collect();
+        if !fills.is_empty() {
+            messages.extend(fills);
+            messages.push(self.positions_update());
+        }
+        Ok(messages)
+    }
+
+    // The rejections of submit, which accepts the order once these pass
+    fn validate(&self, request: &PaperOrderRequest) -> Result<()> {
+        let best = self.books.get(&request.instrument_id).and_then(|book| book.best_opposite(request.is_buy));
+        if request.post_only && best.is_some_and(|price| crosses(request.is_buy, request.price, price)) {
+            return Err(eyre!("POST_ONLY_REJECTED: order {} would cross the book", request.order_id))
+        }
+        if request.price.is_none() && best.is_none() {
+            return Err(eyre!("No book to fill market order {} on instrument {}", request.order_id, request.instrument_id))
+        }
+        Ok(())
+    }
+
+    pub fn cancel(&mut self, order_id: &str) -> Result<Vec<WsResponse>> {
+        match self.orders.get_mut(order_id) {
+            Some(order) if order.is_open() => order.status = "cancelled".to_string(),
+            _ => return Err(eyre!("ORDER_DOES_NOT_EXIST: no open paper order {}", order_id))
+        }
+        Ok(self.order_update(order_id).into_iter().collect())
+    }
+
+    // Returns the cancelled order ids with the order updates
+    pub fn cancel_all(&mut self, filter: &CancelFilter) -> (Vec<String>, Vec<WsResponse>) {
+        let order_ids: Vec<String> = self.orders.values()
+            .filter(|order| order.is_open())
+            .filter(|order| filter.instrument_type.as_ref().is_none_or(|t| *t == order.request.instrument_type))
+            .filter(|order| filter.asset.as_ref().is_none_or(|a| *a == order.request.asset))
+            .filter(|order| filter.instrument.is_none_or(|id| id == order.request.instrument_id))
+            .map(|order| order.request.order_id.clone())
+            .collect();
+
+        let mut messages = Vec::new();
+        for order_id in order_ids.iter() {
+            if let Ok(update) = self.cancel(order_id) {
+                messages.extend(update);
+            }
+        }
+        (order_ids, messages)
+    }
+
+    // Edits cancel the old order and submit the new one, like on the exchange. The new order is
+    // validated first so a rejected edit leaves the old order open.
+    pub fn edit(&mut self, order_id: &str, request: PaperOrderRequest) -> Result<Vec<WsResponse>> {
+        if !self.orders.get(order_id).is_some_and(|order| order.is_open()) {
+            return Err(eyre!("ORDER_DOES_NOT_EXIST: no open paper order {}", order_id))
+        }
+        self.validate(&request)?;
+        let mut messages = self.cancel(order_id)?;
+        messages.extend(self.submit(request)?);
+        Ok(messages)
+    }
+
+    pub fn open_orders(&self) -> Vec<OrderData> {
+        self.orders.values()
+            .filter(|order| order.is_open())
+            .filter_map(|order| self.order_data(&order.request.order_id))
+            .collect()
+    }
+
+    // The order as the REST api would return it
+    pub fn order_data(&self, order_id: &str) -> Option<OrderData> {
+        let order = self.orders.get(order_id)?;
+        Some(OrderData {
+            order_id : order.request.order_id.clone(),
+            account : self.config.account.clone(),
+            instrument_id : order.request.instrument_id,
+            instrument_name : order.request.instrument_name.clone(),
+            instrument_type : order.request.instrument_type.clone(),
+            order_type : if order.request.price.is_some() { "limit".to_string() } else { "market".to_string() },
+            side : order.side().to_string(),
+            amount : order.request.amount,
+            price : order.request.price.or(order.avg_price).unwrap_or_default(),
+            avg_price : order.avg_price,
+            filled : order.filled,
+            order_status : order.status.clone(),
+            post_only : Some(order.request.post_only),
+            reduce_only : Some(false),
+            initial_margin : None,
+            option_type : None,
+            iv : None,
+            expiry : None,
+            strike : None,
+            created_timestamp : Some(order.created),
+            timestamp : self.now(),
+            system_type : "API".to_string(),
+            time_in_force : Some(if order.request.ioc { "IOC".to_string() } else { "GTC".to_string() }),
+            stop : None,
+            trigger : None,
+            close_position : None,
+            partial_position : None,
+            isolated_margin : None,
+            parent_order_id : None,
+            self_trade_prevention : None
+        })
+    }
+
+    // Reply to a websocket create_order or edit_order
+    pub fn order_reply(&self, order_id: &str, id: Option<u64>) -> Option<WsResponse> {
+        let order = self.order_data(order_id)?;
+        Some(WsResponse::PublishResponse {
+            id,
+            data : WsResponseData::CreateEditOrderData {
+                order_id : order.order_id,
+                account : order.account,
+                instrument_id : order.instrument_id,
+                instrument_name : order.instrument_name,
+                instrument_type : order.instrument_type,
+                expiry : None,
+                strike : None,
+                option_type : None,
+                order_type : order.order_type,
+                order_status : order.order_status,
+                side : order.side,
+                amount : order.amount,
+                price : order.price,
+                filled : order.filled,
+                initial_margin : Decimal::ZERO,
+                avg_price : order.avg_price,
+                created_timestamp : order.created_timestamp.unwrap_or(order.timestamp),
+                timestamp : order.timestamp,
+                system_type : order.system_type
+            }
+        })
+    }
+
+    // Fills the order against the opposite side of its book and returns the fill messages
+    fn match_order(&mut self, order_id: &str, maker: bool) -> Vec<WsResponse> {
+        let order = match self.orders.get(order_id) {
+            Some(order) => order.clone(),
+            None => return Vec::new()
+        };
+        let book = match self.books.get_mut(&order.request.instrument_id) {
+            Some(book) => book,
+            None => return Vec::new()
+        };
+
+        let levels = if order.request.is_buy { &mut book.asks } else { &mut book.bids };
+        let mut remaining = order.remaining();
+        let mut trades = Vec::new();
+        for level in levels.iter_mut() {
+            if remaining.is_zero() || !crosses(order.request.is_buy, order.request.price, level.0) {
+                break
+            }
+            let amount = remaining.min(level.1);
+            level.1 -= amount;
+            remaining -= amount;
+            // Resting orders are filled at their own price
+            let price = if maker { order.request.price.unwrap_or(level.0) } else { level.0 };
+            trades.push((price, amount));
+        }
+        levels.retain(|level| !level.1.is_zero());
+
+        trades.into_iter().filter_map(|(price, amount)| self.fill(order_id, price, amount, maker)).collect()
+    }
+
+    // Resting orders move up the queue when the size at their level shrinks
+    fn update_queues(&mut self, instrument_ids: &[u64]) {
+        let books = &self.books;
+        for order in self.orders.values_mut().filter(|o| o.is_open() && instrument_ids.contains(&o.request.instrument_id)) {
+            let level = match (books.get(&order.request.instrument_id), order.request.price) {
+                (Some(book), Some(price)) => book.level_amount(order.request.is_buy, price),
+                _ => None
+            };
+            if let Some(amount) = level {
+                order.queue_ahead = order.queue_ahead.min(amount);
+            }
+        }
+    }
+
+    // A trade fills the resting orders on the other side at or behind its price, best price first.
+    // At the trade price the size queued ahead of an order trades first.
+    fn match_trade(&mut self, instrument_id: u64, taker_buys: bool, price: Decimal, amount: Decimal) -> Vec<WsResponse> {
+        let mut resting: Vec<&PaperOrder> = self.orders.values()
+            .filter(|o| o.is_open() && o.request.instrument_id == instrument_id && o.request.is_buy != taker_buys)
+            .filter(|o| o.request.price.is_some() && crosses(o.request.is_buy, o.request.price, price))
+            .collect();
+        resting.sort_by(|a, b| {
+            let (a_price, b_price) = (a.request.price.unwrap_or_default(), b.request.price.unwrap_or_default());
+            let by_price = if taker_buys { a_price.cmp(&b_price) } else { b_price.cmp(&a_price) };
+            by_price.then(a.created.cmp(&b.created))
+        });
+        let resting: Vec<String> = resting.into_iter().map(|o| o.request.order_id.clone()).collect();
+
+        let mut volume = amount;
+        let mut messages = Vec::new();
+        for order_id in resting {
+            if volume.is_zero() {
+                break
+            }
+            let (limit, filled) = match self.orders.get_mut(&order_id) {
+                Some(order) => {
+                    let limit = order.request.price.unwrap_or(price);
+                    if limit == price {
+                        let ahead = order.queue_ahead.min(volume);
+                        order.queue_ahead -= ahead;
+                        volume -= ahead;
+                    }
+                    let filled = order.remaining().min(volume);
+                    volume -= filled;
+                    (limit, filled)
+                },
+                None => continue
+            };
+            if filled.is_zero() {
+                continue
+            }
+            let fill = self.fill(&order_id, limit, filled, true);
+            messages.extend(self.order_update(&order_id));
+            messages.extend(fill);
+        }
+        messages
+    }
+
+    fn fill(&mut self, order_id: &str, price: Decimal, amount: Decimal, maker: bool) -> Option<WsResponse> {
+        let order = self.orders.get_mut(order_id)?;
+        let filled = order.filled + amount;
+        order.avg_price = Some(match order.avg_price {
+            Some(avg) => (avg * order.filled + price * amount) / filled,
+            None => price
+        });
+        order.filled = filled;
+        order.status = if order.remaining().is_zero() { "filled".to_string() } else { "partial".to_string() };
+
+        let fee_rate = self.config.fee_rate(&order.request.asset, &order.request.instrument_type, maker);
+        let now = self.clock.unwrap_or_else(Utc::now);
+        self.trades += 1;
+        let fill = Fill {
+            trade_id : format!("paper-{}", self.trades),
+            order_id : order_id.to_string(),
+            instrument_id : order.request.instrument_id,
+            instrument_name : order.request.instrument_name.clone(),
+            instrument_type : order.request.instrument_type.clone(),
+            price,
+            side : order.side().to_string(),
+            fees : price * amount * fee_rate,
+            filled : amount,
+            order_status : order.status.clone(),
+            liquidity : if maker { "maker".to_string() } else { "taker".to_string() },
+            created_timestamp : now,
+            system_type : "API".to_string()
+        };
+
+        let request = &order.request;
+        let position = self.positions.entry(request.instrument_id).or_insert_with(|| PaperPosition {
+            instrument_name : request.instrument_name.clone(),
+            instrument_type : request.instrument_type.clone(),
+            asset : request.asset.clone(),
+            size : Decimal::ZERO,
+            avg_entry_price : Decimal::ZERO,
+            last_price : price
+        });
+        position.trade(if request.is_buy { amount } else { -amount }, price);
+
+        Some(subscribe_response("fills", WsResponseData::FillsData { timestamp : now, fill }, now))
+    }
+
+    fn order_update(&self, order_id: &str) -> Option<WsResponse> {
+        let order = self.order_data(order_id)?;
+        let order = Order {
+            order_id : order.order_id,
+            account : order.account,
+            instrument_id : order.instrument_id,
+            instrument_name : order.instrument_name,
+            instrument_type : order.instrument_type,
+            order_type : order.order_type,
+            side : order.side,
+            price : order.price,
+            amount : order.amount,
+            filled : order.filled,
+            order_status : order.order_status,
+            created_timestamp : order.created_timestamp.unwrap_or(order.timestamp),
+            system_type : order.system_type
+        };
+        Some(subscribe_response("orders", WsResponseData::OrdersData { timestamp : self.now(), orders : vec![order] }, self.now()))
+    }
+
+    // Snapshot of every open position, marked at the mid of its book
+    fn positions_update(&self) -> WsResponse {
+        let positions = self.positions.iter()
+            .filter(|(_, position)| !position.size.is_zero())
+            .map(|(instrument_id, position)| {
+                let mark_price = self.books.get(instrument_id).and_then(|book| book.mid()).unwrap_or(position.last_price);
+                Position {
+                    instrument_id : *instrument_id,
+                    instrument_name : position.instrument_name.clone(),
+                    instrument_type : position.instrument_type.clone(),
+                    amount : position.size.abs(),
+                    mark_price,
+                    option : None,
+                    asset : position.asset.clone(),
+                    side : if position.size > Decimal::ZERO { "buy".to_string() } else { "sell".to_string() },
+                    avg_entry_price : position.avg_entry_price,
+                    unrealized_pnl : (mark_price - position.avg_entry_price) * position.size,
+                    maintenance_margin : Decimal::ZERO
+                }
+            })
+            .collect();
+        subscribe_response("positions", WsResponseData::PositionsData { timestamp : self.now(), positions }, self.now())
+    }
+}
+
+// Paper exchange of a client with the channel its synthetic messages are delivered through.
+// read_messages interleaves them with the messages of the exchange.
+#[derive(Debug)]
+pub struct PaperTrading {
+    pub exchange : Mutex<PaperExchange>,
+    tx : UnboundedSender<WsResponse>,
+    rx : Mutex<UnboundedReceiver<WsResponse>>
+}
+
+impl PaperTrading {
+    pub fn new(config: PaperConfig) -> PaperTrading {
+        let (tx, rx) = mpsc::unbounded_channel();
+        PaperTrading { exchange : Mutex::new(PaperExchange::new(config)), tx, rx : Mutex::new(rx) }
+    }
+
+    pub fn publish(&self, messages: Vec<WsResponse>) {
+        for message in messages {
+            if let Err(e) = self.tx.send(message) {
+                error!("Problem publishing paper message: {}", e);
+            }
+        }
+    }
+
+    pub(crate) async fn next(&self) -> Option<WsResponse> {
+        self.rx.lock().await.recv().await
+    }
+}
+
+fn paper_order_id() -> String {
+    let bytes: [u8; 32] = rand::random();
+    format!("0x{}", bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+// In paper mode the order ops below replace the ones of the exchange. Orders are still rounded and
+// risk checked, but are neither signed nor sent, so no credentials are needed. The instruments
+// traded have to be loaded with load_instruments and the orderbook or book ticker of each has to be
+// subscribed with read_messages running.
+impl AevoClient {
+    pub fn enable_paper_trading(&mut self, config: PaperConfig) {
+        info!("Paper trading enabled for account {}", config.account);
+        self.paper = Some(std::sync::Arc::new(PaperTrading::new(config)));
+    }
+
+    pub fn is_paper_trading(&self) -> bool {
+        self.paper.is_some()
+    }
+
+    fn paper(&self) -> Result<&PaperTrading> {
+        self.paper.as_deref().ok_or_else(|| eyre!("Paper trading is not enabled"))
+    }
+
+    async fn paper_request(&self, params: &PaperOrderParams<'_>) -> Result<(PaperOrderRequest, RiskReservation)> {
+        let PaperOrderParams { replaces, instrument_id, is_buy, post_only, ioc, .. } = *params;
+        let (limit_price, quantity) = self.prepare_order(instrument_id, is_buy, params.limit_price, params.quantity).await?;
+        let reservation = self.check_risk(instrument_id, is_buy, limit_price, quantity, replaces).await?;
+
+        let instrument = self.instruments.read().await.get(instrument_id).cloned()
+            .ok_or_else(|| eyre!("Instrument {} is not loaded", instrument_id))?;
+
+        let request = PaperOrderRequest {
+            order_id : paper_order_id(),
+            instrument_id,
+            instrument_name : instrument.instrument_name,
+            instrument_type : instrument.instrument_type,
+            asset : instrument.underlying_asset,
+            is_buy,
+            price : limit_price.map(to_decimal).transpose()?,
+            amount : to_decimal(quantity)?,
+            post_only,
+            ioc
+        };
+        Ok((request, reservation))
+    }
+
+    // Websocket create_order and edit_order: rejections are reported through the order manager and
+    // an error reply, like the exchange does
+    pub(crate) async fn paper_create_order(&self, params: PaperOrderParams<'_>, id: Option<u64>) -> Result<String> {
+        let paper = self.paper()?;
+        let (request, reservation) = self.paper_request(&params).await?;
+        let order_id = request.order_id.clone();
+        self.orders.lock().await.record_submission(Submission {
+            order_id : order_id.clone(),
+            instrument_id : params.instrument_id,
+            is_buy : params.is_buy,
+            price : request.price,
+            amount : request.amount,
+            request_id : id,
+            replaces : params.replaces.map(str::to_string)
+        });
+        drop(reservation);
+
+        let mut exchange = paper.exchange.lock().await;
+        let result = match params.replaces {
+            Some(old_order_id) => exchange.edit(old_order_id, request),
+            None => exchange.submit(request)
+        };
+        match result {
+            Ok(messages) => {
+                paper.publish(exchange.order_reply(&order_id, id).into_iter().collect());
+                paper.publish(messages);
+            },
+            Err(e) => {
+                self.orders.lock().await.mark_rejected(&order_id, &e.to_string());
+                paper.publish(vec![WsResponse::ErrorResponse { id, error : e.to_string() }]);
+            }
+        }
+        Ok(order_id)
+    }
+
+    // REST create and edit: rejections are returned as errors
+    pub(crate) async fn paper_rest_create_order(&self, params: PaperOrderParams<'_>) -> Result<OrderData> {
+        let paper = self.paper()?;
+        let (request, _reservation) = self.paper_request(&params).await?;
+        let order_id = request.order_id.clone();
+
+        let mut exchange = paper.exchange.lock().await;
+        let messages = match params.replaces {
+            Some(old_order_id) => exchange.edit(old_order_id, request)?,
+            None => exchange.submit(request)?
+        };
+        paper.publish(messages);
+
+        let data = exchange.order_data(&order_id).ok_or_else(|| eyre!("Paper order {} not found", order_id))?;
+        {
+            let mut orders = self.orders.lock().await;
+            orders.apply_order_data(&data);
+            if let Some(old_order_id) = params.replaces {
+                orders.mark_cancelled(old_order_id);
+            }
+        }
+        Ok(data)
+    }
+
+    pub(crate) async fn paper_cancel_order(&self, order_id: &str) -> Result<()> {
+        let paper = self.paper()?;
+        let messages = paper.exchange.lock().await.cancel(order_id)?;
+        paper.publish(messages);
+        self.orders.lock().await.mark_cancelled(order_id);
+        Ok(())
+    }
+
+    pub(crate) async fn paper_cancel_all_orders(&self, filter: &CancelFilter) -> Result<Vec<String>> {
+        let paper = self.paper()?;
+        let (order_ids, messages) = paper.exchange.lock().await.cancel_all(filter);
+        paper.publish(messages);
+        {
+            let mut orders = self.orders.lock().await;
+            for order_id in order_ids.iter() {
+                orders.mark_cancelled(order_id);
+            }
+        }
+        Ok(order_ids)
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{HashMap, HashSet};
+use chrono::Utc;
+use log::info;
+use eyre::{eyre, Result};
+use tokio_tungstenite::tungstenite::Message;
+use crate::{
+    aevo::AevoClient,
+    rest::{GetPortfolioData, RestResponse},
+    types::{Decimal, Timestamp},
+    ws_structs::{Fill, Position, Ticker, WsRequest, WsRequestData, WsResponse, WsResponseData}
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackedPosition {
+    pub instrument_id : u64,
+    pub instrument_name : String,
+    // Positive for long, negative for short
+    pub size : Decimal,
+    pub avg_entry_price : Decimal,
+    pub mark_price : Option<Decimal>,
+    pub realized_pnl : Decimal,
+    // Positive when paid, negative for rebates
+    pub fees : Decimal,
+    pub updated : Timestamp
+}
+
+impl TrackedPosition {
+    fn new(instrument_id: u64, instrument_name: &str) -> TrackedPosition {
+        TrackedPosition {
+            instrument_id,
+            instrument_name : instrument_name.to_string(),
+            size : Decimal::ZERO,
+            avg_entry_price : Decimal::ZERO,
+            mark_price : None,
+            realized_pnl : Decimal::ZERO,
+            fees : Decimal::ZERO,
+            updated : Utc::now()
+        }
+    }
+
+    pub fn unrealized_pnl(&self) -> Decimal {
+        match self.mark_price {
+            Some(mark) => (mark - self.avg_entry_price) * self.size,
+            None => Decimal::ZERO
+        }
+    }
+
+    // Realized plus unrealized trading pnl, net of fees
+    pub fn net_pnl(&self) -> Decimal {
+        self.realized_pnl + self.unrealized_pnl() - self.fees
+    }
+
+    pub fn notional(&self) -> Decimal {
+        self.size.abs() * self.mark_price.unwrap_or(self.avg_entry_price)
+    }
+
+    // Average cost accounting: trades that reduce the position realize pnl against the entry
+    // price, a trade that flips the position opens the remainder at the trade price
+    fn trade(&mut self, signed_amount: Decimal, price: Decimal) {
+        if signed_amount.is_zero() {
+            return
+        }
+
+        let same_direction = self.size.is_zero() || self.size.is_sign_positive() == signed_amount.is_sign_positive();
+
+        if same_direction {
+            let size = self.size + signed_amount;
+            self.avg_entry_price = (self.avg_entry_price * self.size.abs() + price * signed_amount.abs()) / size.abs();
+            self.size = size;
+        } else {
+            let closed = signed_amount.abs().min(self.size.abs());
+            let direction = if self.size.is_sign_positive() { Decimal::ONE } else { -Decimal::ONE };
+            self.realized_pnl += (price - self.avg_entry_price) * closed * direction;
+            self.size += signed_amount;
+
+            if self.size.is_zero() {
+                self.avg_entry_price = Decimal::ZERO;
+            } else if self.size.is_sign_positive() != direction.is_sign_positive() {
+                self.avg_entry_price = price;
+            }
+        }
+        self.updated = Utc::now();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PnlReconciliation {
+    pub local_realized_pnl : Decimal,
+    pub exchange_realized_pnl : Decimal,
+    pub local_unrealized_pnl : Decimal,
+    pub exchange_pnl : Decimal
+}
+
+impl PnlReconciliation {
+    pub fn realized_difference(&self) -> Decimal {
+        self.local_realized_pnl - self.exchange_realized_pnl
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PositionTracker {
+    positions : HashMap<u64, TrackedPosition>,
+    seen_trades : HashSet<String>
+}
+
+impl PositionTracker {
+    pub fn new() -> PositionTracker {
+        PositionTracker::default()
+    }
+
+    pub fn apply_fill(&mut self, fill: &Fill) {
+        if !self.seen_trades.insert(fill.trade_id.clone()) {
+            return
+        }
+
+        let position = self.positions.entry(fill.instrument_id)
+            .or_insert_with(|| TrackedPosition::new(fill.instrument_id, &fill.instrument_name));
+        let signed_amount = if fill.side == "buy" { fill.filled } else { -fill.filled };
+        position.trade(signed_amount, fill.price);
+        position.fees += fill.fees;
+    }
+
+    // Positions snapshots are authoritative for size and entry price. Instruments missing from the
+    // snapshot have been closed.
+    pub fn apply_positions(&mut self, positions: &[Position]) {
+        let open: HashSet<u64> = positions.iter().map(|p| p.instrument_id).collect();
+
+        for snapshot in positions {
+            let position = self.positions.entry(snapshot.instrument_id)
+                .or_insert_with(|| TrackedPosition::new(snapshot.instrument_id, &snapshot.instrument_name));
+            let size = if snapshot.side == "sell" { -snapshot.amount } else { snapshot.amount };
+
+            if size != position.size || snapshot.avg_entry_price != position.avg_entry_price {
+                info!("Position {} reset from snapshot: size {} -> {}", snapshot.instrument_name, position.size, size);
+            }
+            position.size = size;
+            position.avg_entry_price = snapshot.avg_entry_price;
+            position.mark_price = Some(snapshot.mark_price);
+            position.updated = Utc::now();
+        }
+
+        for position in self.positions.values_mut() {
+            if !open.contains(&position.instrument_id) && !position.size.is_zero() {
+                position.size = Decimal::ZERO;
+                position.avg_entry_price = Decimal::ZERO;
+                position.updated = Utc::now();
+            }
+        }
+    }
+
+    pub fn update_mark(&mut self, instrument_id: u64, mark_price: Decimal) {
+        if let Some(position) = self.positions.get_mut(&instrument_id) {
+            position.mark_price = Some(mark_price);
+        }
+    }
+
+    pub fn apply_tickers(&mut self, tickers: &[Ticker]) {
+        for ticker in tickers {
+            self.update_mark(ticker.instrument_id, ticker.mark.price);
+        }
+    }
+
+    // Feeds a message from read_messages into the tracker
+    pub fn apply(&mut self, response: &WsResponse) {
+        if let WsResponse::SubscribeResponse { data, .. } = response {
+            match data {
+                WsResponseData::FillsData { fill, .. } => self.apply_fill(fill),
+                WsResponseData::PositionsData { positions, .. } => self.apply_positions(positions),
+                WsResponseData::TickerData { tickers, .. } => self.apply_tickers(tickers),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn get(&self, instrument_id: u64) -> Option<&TrackedPosition> {
+        self.positions.get(&instrument_id)
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &TrackedPosition> {
+        self.positions.values()
+    }
+
+    pub fn open_positions(&self) -> Vec<&TrackedPosition> {
+        self.positions.values().filter(|p| !p.size.is_zero()).collect()
+    }
+
+    pub fn realized_pnl(&self) -> Decimal {
+        self.positions.values().map(|p| p.realized_pnl).sum()
+    }
+
+    pub fn unrealized_pnl(&self) -> Decimal {
+        self.positions.values().map(|p| p.unrealized_pnl()).sum()
+    }
+
+    pub fn fees(&self) -> Decimal {
+        self.positions.values().map(|p| p.fees).sum()
+    }
+
+    pub fn reconcile(&self, portfolio: &GetPortfolioData) -> PnlReconciliation {
+        PnlReconciliation {
+            local_realized_pnl : self.realized_pnl(),
+            exchange_realized_pnl : portfolio.realized_pnl,
+            local_unrealized_pnl : self.unrealized_pnl(),
+            exchange_pnl : portfolio.pnl
+        }
+    }
+}
+
+impl AevoClient {
+    pub async fn subscribe_positions(&self) -> Result<()> {
+        let request = WsRequest {
+            op : "subscribe".to_string(),
+            data : WsRequestData::ChannelData(vec!["positions".to_string()]),
+            id: None
+        };
+
+        let msg = Message::from(serde_json::to_string(&request)?);
+        self.send(&msg).await
+    }
+
+    // Resets sizes from the account positions and compares locally tracked pnl with the portfolio
+    pub async fn reconcile_positions(&self) -> Result<PnlReconciliation> {
+        info!("Reconciling positions with the exchange");
+        match self.rest_get_account().await? {
+            RestResponse::GetAccount(account) => self.positions.lock().await.apply_positions(&account.positions),
+            response => return Err(eyre!("Unexpected account response: {:?}", response))
+        }
+
+        match self.rest_get_portfolio().await? {
+            RestResponse::GetPortfolio(portfolio) => {
+                let reconciliation = self.positions.lock().await.reconcile(&portfolio);
+                info!(
+                    "Realized pnl local {} exchange {}",
+                    reconciliation.local_realized_pnl, reconciliation.exchange_realized_pnl
+                );
+                Ok(reconciliation)
+            },
+            response => Err(eyre!("Unexpected portfolio response: {:?}", response))
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use rust_decimal::prelude::ToPrimitive;
+use tokio::sync::mpsc::UnboundedReceiver;
+use crate::{
+    aevo::AevoClient,
+    instruments::Instrument,
+    order_manager::{OrderEvent, OrderState},
+    types::Decimal
+};
+
+// Source of the price quotes are centred on. Returning None pulls the quotes of the instrument.
+pub trait FairValue: Send + Sync {
+    fn fair_value(&self, instrument_id: u64) -> Option<Decimal>;
+}
+
+impl<F> FairValue for F
+where
+    F: Fn(u64) -> Option<Decimal> + Send + Sync
+{
+    fn fair_value(&self, instrument_id: u64) -> Option<Decimal> {
+        self(instrument_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteParams {
+    pub instrument_id : u64,
+    // Distance of each quote from the fair value
+    pub half_spread : Decimal,
+    pub bid_size : Decimal,
+    pub ask_size : Decimal,
+    // Resting quotes are only moved once the target moves by at least this much
+    pub requote_threshold : Decimal,
+    // Sent with every quote so the account's MMP settings apply to it
+    pub mmp : bool
+}
+
+impl QuoteParams {
+    pub fn new(instrument_id: u64, half_spread: Decimal, size: Decimal) -> QuoteParams {
+        QuoteParams {
+            instrument_id,
+            half_spread,
+            bid_size : size,
+            ask_size : size,
+            requote_threshold : Decimal::ZERO,
+            mmp : true
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuoteSide {
+    Bid,
+    Ask
+}
+
+impl QuoteSide {
+    pub fn is_buy(&self) -> bool {
+        matches!(self, QuoteSide::Bid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveQuote {
+    pub order_id : String,
+    pub price : Decimal,
+    pub size : Decimal,
+    pub remaining : Decimal,
+    // The quote an edit is replacing until the exchange acknowledges the edit. Its order keeps
+    // resting when the edit is rejected.
+    pub replaces : Option<Box<ActiveQuote>>
+}
+
+impl ActiveQuote {
+    pub fn new(order_id: String, price: Decimal, size: Decimal) -> ActiveQuote {
+        ActiveQuote { order_id, price, size, remaining : size, replaces : None }
+    }
+
+    // The quote after an event for its order or the order it replaces, None once nothing rests
+    pub fn follow(self, order_id: &str, state: OrderState, remaining: Decimal) -> Option<ActiveQuote> {
+        if self.order_id == order_id {
+            match state {
+                OrderState::Pending => Some(self),
+                OrderState::Acknowledged | OrderState::PartiallyFilled => Some(ActiveQuote { remaining, replaces : None, ..self }),
+                OrderState::Filled | OrderState::Cancelled => None,
+                OrderState::Rejected => self.replaces.map(|replaced| *replaced)
+            }
+        } else if self.replaces.as_ref().is_some_and(|replaced| replaced.order_id == order_id) && state.is_terminal() {
+            Some(ActiveQuote { replaces : None, ..self })
+        } else {
+            Some(self)
+        }
+    }
+
+    fn involves(&self, order_id: &str) -> bool {
+        self.order_id == order_id || self.replaces.as_ref().is_some_and(|replaced| replaced.order_id == order_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuoteAction {
+    Keep,
+    Place { price : Decimal, size : Decimal },
+    Replace { order_id : String, price : Decimal, size : Decimal },
+    Cancel { order_id : String }
+}
+
+// Decides how to move a resting quote towards its target price and size. Partially filled quotes
+// are topped back up to the target size. A quote with an edit in flight is only moved once the
+// exchange has answered the edit.
+pub fn plan_quote(active: Option<&ActiveQuote>, target: Option<(Decimal, Decimal)>, threshold: Decimal) -> QuoteAction {
+    match (active, target) {
+        (None, None) => QuoteAction::Keep,
+        (Some(quote), None) => QuoteAction::Cancel { order_id : quote.order_id.clone() },
+        (None, Some((price, size))) => QuoteAction::Place { price, size },
+        (Some(quote), Some(_)) if quote.replaces.is_some() => QuoteAction::Keep,
+        (Some(quote), Some((price, size))) => {
+            let moved = price != quote.price && (price - quote.price).abs() >= threshold;
+            if moved || size != quote.size || quote.remaining < quote.size {
+                QuoteAction::Replace { order_id : quote.order_id.clone(), price, size }
+            } else {
+                QuoteAction::Keep
+            }
+        }
+    }
+}
+
+// Target price and size of one side, rounded to the instrument's tick and lot. Every post-only
+// rejection in a row moves the quote one tick further from the fair value.
+pub fn quote_target(
+    params: &QuoteParams,
+    side: QuoteSide,
+    fair_value: Decimal,
+    instrument: Option<&Instrument>,
+    rejections: u32
+) -> Option<(Decimal, Decimal)> {
+    let tick = instrument.map(|i| i.price_step).unwrap_or_default();
+    let offset = params.half_spread + tick * Decimal::from(rejections);
+    let (price, size) = match side {
+        QuoteSide::Bid => (fair_value - offset, params.bid_size),
+        QuoteSide::Ask => (fair_value + offset, params.ask_size)
+    };
+
+    let (price, size) = match instrument {
+        Some(instrument) => (instrument.round_price(price, side.is_buy()), instrument.round_amount(size)),
+        None => (price, size)
+    };
+
+    if price <= Decimal::ZERO || size <= Decimal::ZERO {
+        return None
+    }
+    Some((price, size))
+}
+
+fn is_post_only_rejection(reason: Option<&str>) -> bool {
+    reason.is_some_and(|r| r.to_lowercase().contains("post"))
+}
+
+// Keeps a bid and an ask around a fair value for every configured instrument. Quotes are placed
+// post-only and moved with edit_order, fills and cancels are picked up from the order manager so
+// read_messages has to be running.
+pub struct QuotingEngine {
+    client : Arc<AevoClient>,
+    fair_value : Box<dyn FairValue>,
+    params : HashMap<u64, QuoteParams>,
+    quotes : HashMap<(u64, QuoteSide), ActiveQuote>,
+    rejections : HashMap<(u64, QuoteSide), u32>,
+    events : UnboundedReceiver<OrderEvent>
+}
+
+impl QuotingEngine {
+    pub async fn new(client: Arc<AevoClient>, fair_value: Box<dyn FairValue>) -> QuotingEngine {
+        let events = client.orders.lock().await.subscribe();
+        QuotingEngine {
+            client,
+            fair_value,
+            params : HashMap::new(),
+            quotes : HashMap::new(),
+            rejections : HashMap::new(),
+            events
+        }
+    }
+
+    pub fn set_params(&mut self, params: QuoteParams) {
+        self.params.insert(params.instrument_id, params);
+    }
+
+    pub fn quote(&self, instrument_id: u64, side: QuoteSide) -> Option<&ActiveQuote> {
+        self.quotes.get(&(instrument_id, side))
+    }
+
+    pub async fn remove_instrument(&mut self, instrument_id: u64) {
+        self.params.remove(&instrument_id);
+        for side in [QuoteSide::Bid, QuoteSide::Ask] {
+            self.cancel_quote(instrument_id, side).await;
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &OrderEvent) {
+        let key = match self.quotes.iter().find(|(_, quote)| quote.involves(&event.order.order_id)) {
+            Some((key, _)) => *key,
+            None => return
+        };
+        let is_current = self.quotes.get(&key).is_some_and(|quote| quote.order_id == event.order.order_id);
+
+        if let Some(quote) = self.quotes.remove(&key) {
+            if let Some(quote) = quote.follow(&event.order.order_id, event.order.state, event.order.remaining()) {
+                self.quotes.insert(key, quote);
+            }
+        }
+
+        if !is_current {
+            return
+        }
+        match event.order.state {
+            OrderState::Acknowledged | OrderState::PartiallyFilled => {
+                self.rejections.remove(&key);
+            },
+            OrderState::Rejected => {
+                if is_post_only_rejection(event.order.reject_reason.as_deref()) {
+                    *self.rejections.entry(key).or_default() += 1;
+                } else {
+                    warn!("Quote {} rejected: {:?}", event.order.order_id, event.order.reject_reason);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // Applies pending order events, then moves every quote towards its target
+    pub async fn requote(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            self.handle_event(&event);
+        }
+
+        let params: Vec<QuoteParams> = self.params.values().cloned().collect();
+        for params in params {
+            let instrument = self.client.instruments.read().await.get(params.instrument_id).cloned();
+            // Quotes are pulled while MMP has the asset frozen, the exchange would reject them
+            let frozen = match &instrument {
+                Some(instrument) if params.mmp => self.client.mmp.lock().await.is_frozen(&instrument.underlying_asset),
+                _ => false
+            };
+            let fair_value = if frozen { None } else { self.fair_value.fair_value(params.instrument_id) };
+
+            for side in [QuoteSide::Bid, QuoteSide::Ask] {
+                let key = (params.instrument_id, side);
+                let rejections = self.rejections.get(&key).copied().unwrap_or_default();
+                let target = fair_value.and_then(|fair| quote_target(&params, side, fair, instrument.as_ref(), rejections));
+                let action = plan_quote(self.quotes.get(&key), target, params.requote_threshold);
+                self.execute(params.instrument_id, side, action).await;
+            }
+        }
+    }
+
+    async fn execute(&mut self, instrument_id: u64, side: QuoteSide, action: QuoteAction) {
+        let key = (instrument_id, side);
+        let mmp = self.params.get(&instrument_id).map(|p| p.mmp);
+
+        let result = match action {
+            QuoteAction::Keep => return,
+            QuoteAction::Place { price, size } => {
+                self.place(instrument_id, side, price, size, mmp).await
+            },
+            QuoteAction::Replace { order_id, price, size } => {
+                self.replace(instrument_id, side, order_id, price, size, mmp).await
+            },
+            QuoteAction::Cancel { order_id } => {
+                // The order an edit in flight replaces rests on if the edit is rejected
+                if let Some(replaced) = self.quotes.remove(&key).and_then(|quote| quote.replaces) {
+                    if let Err(e) = self.client.cancel_order(replaced.order_id.clone()).await {
+                        warn!("Problem cancelling replaced quote {}: {}", replaced.order_id, e);
+                    }
+                }
+                self.client.cancel_order(order_id).await
+            }
+        };
+
+        if let Err(e) = result {
+            error!("Problem updating {:?} quote on instrument {}: {}", side, instrument_id, e);
+        }
+    }
+
+    async fn place(&mut self, instrument_id: u64, side: QuoteSide, price: Decimal, size: Decimal, mmp: Option<bool>) -> Result<()> {
+        let order_id = self.client.create_order(
+            instrument_id,
+            side.is_buy(),
+            price.to_f64().ok_or_else(|| eyre!("Price {} out of range", price))?,
+            size.to_f64().ok_or_else(|| eyre!("Size {} out of range", size))?,
+            Some(true),
+            Some(self.client.replies.next_id()),
+            mmp
+        ).await?;
+
+        self.quotes.insert((instrument_id, side), ActiveQuote::new(order_id, price, size));
+        Ok(())
+    }
+
+    async fn replace(
+        &mut self,
+        instrument_id: u64,
+        side: QuoteSide,
+        order_id: String,
+        price: Decimal,
+        size: Decimal,
+        mmp: Option<bool>
+    ) -> Result<()> {
+        let new_order_id = self.client.edit_order(
+            order_id.clone(),
+            instrument_id,
+            side.is_buy(),
+            price.to_f64().ok_or_else(|| eyre!("Price {} out of range", price))?,
+            size.to_f64().ok_or_else(|| eyre!("Size {} out of range", size))?,
+            Some(self.client.replies.next_id()),
+            Some(true),
+            mmp
+        ).await?;
+
+        // Kept until the edit is acknowledged, the old order still rests if it is rejected
+        let replaced = self.quotes.remove(&(instrument_id, side))
+            .filter(|quote| quote.order_id == order_id)
+            .map(|quote| Box::new(ActiveQuote { replaces : None, ..quote }));
+        self.quotes.insert((instrument_id, side), ActiveQuote { replaces : replaced, ..ActiveQuote::new(new_order_id, price, size) });
+        Ok(())
+    }
+
+    pub async fn cancel_quote(&mut self, instrument_id: u64, side: QuoteSide) {
+        if let Some(quote) = self.quotes.get(&(instrument_id, side)) {
+            let action = QuoteAction::Cancel { order_id : quote.order_id.clone() };
+            self.execute(instrument_id, side, action).await;
+        }
+    }
+
+    pub async fn cancel_quotes(&mut self) {
+        let keys: Vec<(u64, QuoteSide)> = self.quotes.keys().copied().collect();
+        for (instrument_id, side) in keys {
+            self.cancel_quote(instrument_id, side).await;
+        }
+    }
+
+    // Requotes every period and reacts to order events as they arrive until the task is dropped
+    pub async fn run(&mut self, period: Duration) {
+        info!("Starting quoting engine on {} instruments", self.params.len());
+        let mut interval = tokio::time::interval(period);
+        loop {
+            tokio::select! {
+                Some(event) = self.events.recv() => self.handle_event(&event),
+                _ = interval.tick() => self.requote().await
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use log::{debug, warn};
+use eyre::{eyre, Result};
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+use tokio_tungstenite::tungstenite::Message;
+use crate::aevo::AevoClient;
+
+// Wait applied after a 429 that did not carry a usable Retry-After header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitClass {
+    // Market data that needs no api key
+    Public,
+    // Authenticated reads and account operations
+    Private,
+    // Order creation, edits and cancels
+    Order
+}
+
+impl RateLimitClass {
+    // Classifies an outgoing websocket request from its op and channels
+    pub fn of_ws_message(data: &Message) -> RateLimitClass {
+        let request = match data {
+            Message::Text(text) => serde_json::from_str::<serde_json::Value>(text).unwrap_or_default(),
+            _ => return RateLimitClass::Public
+        };
+
+        match request["op"].as_str().unwrap_or_default() {
+            "create_order" | "edit_order" | "cancel_order" | "cancel_all_orders" => RateLimitClass::Order,
+            "auth" => RateLimitClass::Private,
+            "subscribe" | "unsubscribe" => {
+                let private = request["data"].as_array()
+                    .map(|channels| channels.iter().any(|c| matches!(c.as_str(), Some("orders" | "fills" | "positions"))))
+                    .unwrap_or(false);
+                if private { RateLimitClass::Private } else { RateLimitClass::Public }
+            },
+            _ => RateLimitClass::Public
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketConfig {
+    // Burst size
+    pub capacity : f64,
+    pub refill_per_second : f64
+}
+
+// Defaults are conservative, tune them to the limits of the api key
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitConfig {
+    pub public : BucketConfig,
+    pub private : BucketConfig,
+    pub orders : BucketConfig,
+    // Times a REST call answered with 429 is retried after waiting out Retry-After
+    pub max_retries : u32
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            public : BucketConfig { capacity : 20.0, refill_per_second : 10.0 },
+            private : BucketConfig { capacity : 10.0, refill_per_second : 5.0 },
+            orders : BucketConfig { capacity : 20.0, refill_per_second : 10.0 },
+            max_retries : 2
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn bucket(&self, class: RateLimitClass) -> BucketConfig {
+        match class {
+            RateLimitClass::Public => self.public,
+            RateLimitClass::Private => self.private,
+            RateLimitClass::Order => self.orders
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    pub config : BucketConfig,
+    tokens : f64,
+    last_refill : Instant,
+    // Set from Retry-After, no tokens are handed out before this
+    blocked_until : Option<Instant>
+}
+
+impl TokenBucket {
+    pub fn new(config: BucketConfig, now: Instant) -> TokenBucket {
+        TokenBucket { config, tokens : config.capacity, last_refill : now, blocked_until : None }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.config.refill_per_second).min(self.config.capacity);
+        self.last_refill = now;
+    }
+
+    // Takes a token or returns how long to wait before trying again
+    pub fn try_acquire(&mut self, now: Instant) -> std::result::Result<(), Duration> {
+        if let Some(until) = self.blocked_until {
+            if now < until {
+                return Err(until - now)
+            }
+            self.blocked_until = None;
+        }
+
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if self.config.refill_per_second > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.config.refill_per_second))
+        } else {
+            Err(DEFAULT_RETRY_AFTER)
+        }
+    }
+
+    // Drains the bucket and blocks it until the server says requests are accepted again
+    pub fn block_for(&mut self, wait: Duration, now: Instant) {
+        let until = now + wait;
+        self.tokens = 0.0;
+        self.last_refill = until;
+        self.blocked_until = Some(self.blocked_until.map_or(until, |current| current.max(until)));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ThrottleMetrics {
+    pub requests : u64,
+    // Calls that had to wait for a token
+    pub throttled : u64,
+    pub throttled_time : Duration,
+    // 429 responses received from the exchange
+    pub rate_limited : u64
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    config : RateLimitConfig,
+    buckets : Mutex<HashMap<RateLimitClass, TokenBucket>>,
+    metrics : Mutex<HashMap<RateLimitClass, ThrottleMetrics>>
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        let now = Instant::now();
+        let buckets = [RateLimitClass::Public, RateLimitClass::Private, RateLimitClass::Order].into_iter()
+            .map(|class| (class, TokenBucket::new(config.bucket(class), now)))
+            .collect();
+
+        RateLimiter { config, buckets : Mutex::new(buckets), metrics : Mutex::new(HashMap::new()) }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    pub fn try_acquire(&self, class: RateLimitClass) -> std::result::Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(class)
+            .or_insert_with(|| TokenBucket::new(self.config.bucket(class), Instant::now()));
+        bucket.try_acquire(Instant::now())
+    }
+
+    // Waits until a token of the class is available
+    pub async fn acquire(&self, class: RateLimitClass) {
+        let start = Instant::now();
+        let mut throttled = false;
+
+        while let Err(wait) = self.try_acquire(class) {
+            if !throttled {
+                debug!("{:?} rate limit reached, waiting {:?}", class, wait);
+                throttled = true;
+            }
+            tokio::time::sleep(wait).await;
+        }
+
+        let mut metrics = self.metrics.lock().unwrap();
+        let entry = metrics.entry(class).or_default();
+        entry.requests += 1;
+        if throttled {
+            entry.throttled += 1;
+            entry.throttled_time += start.elapsed();
+        }
+    }
+
+    pub fn record_rate_limited(&self, class: RateLimitClass, retry_after: Duration) {
+        warn!("{:?} request rate limited by the exchange, retrying after {:?}", class, retry_after);
+        if let Some(bucket) = self.buckets.lock().unwrap().get_mut(&class) {
+            bucket.block_for(retry_after, Instant::now());
+        }
+        self.metrics.lock().unwrap().entry(class).or_default().rate_limited += 1;
+    }
+
+    pub fn metrics(&self, class: RateLimitClass) -> ThrottleMetrics {
+        self.metrics.lock().unwrap().get(&class).copied().unwrap_or_default()
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig::default())
+    }
+}
+
+// Retry-After is sent as a number of seconds by the exchange
+fn retry_after(response: &Response) -> Duration {
+    response.headers().get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+}
+
+impl AevoClient {
+    // Sends a REST request through the limiter of its class. Requests answered with 429 wait out
+    // Retry-After and are retried, so rate limiting surfaces as an error instead of a decode failure.
+    pub(crate) async fn send_rest(&self, class: RateLimitClass, request: RequestBuilder) -> Result<Response> {
+        let max_retries = self.rate_limiter.config().max_retries;
+        let mut attempts = 0;
+
+        loop {
+            let attempt = request.try_clone()
+                .ok_or_else(|| eyre!("Request can not be retried"))?;
+            self.rate_limiter.acquire(class).await;
+            let response = attempt.send().await?;
+
+            if response.status() != StatusCode::TOO_MANY_REQUESTS {
+                return Ok(response)
+            }
+
+            let wait = retry_after(&response);
+            self.rate_limiter.record_rate_limited(class, wait);
+            if attempts >= max_retries {
+                return Err(eyre!("{:?} request rate limited after {} retries", class, attempts))
+            }
+            attempts += 1;
+        }
+    }
+
+    // Replaces the limiter, call before the client is shared between tasks
+    pub fn set_rate_limits(&mut self, config: RateLimitConfig) {
+        self.rate_limiter = Arc::new(RateLimiter::new(config));
+    }
+
+    pub fn rate_limit_metrics(&self, class: RateLimitClass) -> ThrottleMetrics {
+        self.rate_limiter.metrics(class)
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::{self, Receiver, SyncSender, TrySendError}, Arc, Mutex},
+    thread::{self, JoinHandle}
+};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use serde_derive::{Deserialize, Serialize};
+use serde_with::serde_as;
+use crate::{
+    aevo::AevoClient,
+    types::{NanosStr, Timestamp},
+    ws_structs::WsResponse
+};
+
+// Channels recorded unless configured otherwise
+pub const DEFAULT_CHANNELS: [&str; 6] = ["orderbook", "trades", "index", "ticker", "book-ticker", "fills"];
+
+// Gzip output is flushed every this many records so a crash loses little
+const FLUSH_EVERY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    // Gzip compressed newline delimited JSON, one RecordedMessage per line
+    Ndjson,
+    // Columns channel, exchange_ts and received in nanoseconds, and message as JSON. A file is only
+    // readable once it has been rotated or the recorder stopped.
+    #[cfg(feature = "parquet")]
+    Parquet
+}
+
+impl RecordFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Ndjson => "ndjson.gz",
+            #[cfg(feature = "parquet")]
+            RecordFormat::Parquet => "parquet"
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderConfig {
+    pub directory : PathBuf,
+    pub format : RecordFormat,
+    // Channel names without their asset or instrument part, e.g. "orderbook" for "orderbook:ETH-PERP"
+    pub channels : Vec<String>,
+    // Messages queued for the writer, further messages are dropped while the queue is full
+    pub capacity : usize
+}
+
+impl RecorderConfig {
+    pub fn new(directory: impl Into<PathBuf>) -> RecorderConfig {
+        RecorderConfig {
+            directory : directory.into(),
+            format : RecordFormat::Ndjson,
+            channels : DEFAULT_CHANNELS.iter().map(|c| c.to_string()).collect(),
+            capacity : 100_000
+        }
+    }
+
+    fn records(&self, channel: &str) -> bool {
+        let name = channel.split(':').next().unwrap_or(channel);
+        self.channels.iter().any(|c| c == name)
+    }
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedMessage {
+    pub channel : String,
+    // write_ts set by the exchange
+    #[serde_as(as = "Option<NanosStr>")]
+    pub exchange_ts : Option<Timestamp>,
+    // When read_messages received the message
+    #[serde_as(as = "NanosStr")]
+    pub received : Timestamp,
+    pub message : WsResponse
+}
+
+// Queues subscription messages for a writer thread. Recording never blocks the read loop, messages
+// are dropped and counted when the writer falls behind.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    config : Arc<RecorderConfig>,
+    tx : SyncSender<RecordedMessage>,
+    dropped : Arc<AtomicU64>,
+    // Set once a message found the writer stopped, so that is only logged once
+    stopped : Arc<AtomicBool>,
+    error : Arc<Mutex<Option<String>>>
+}
+
+impl Recorder {
+    // Starts the writer thread. It stops once every Recorder clone is dropped, after closing the
+    // current file, and returns the first write error it ran into.
+    pub fn start(config: RecorderConfig) -> Result<(Recorder, JoinHandle<Result<()>>)> {
+        fs::create_dir_all(&config.directory)?;
+        let (tx, rx) = mpsc::sync_channel(config.capacity);
+        let config = Arc::new(config);
+
+        let error = Arc::new(Mutex::new(None));
+
+        let writer_config = config.clone();
+        let writer_error = error.clone();
+        let handle = thread::Builder::new()
+            .name("aevo-recorder".to_string())
+            .spawn(move || {
+                // The error is stored before rx is dropped, so a sender that finds the writer gone can read it
+                let result = write_records(&rx, &writer_config);
+                if let Err(e) = &result {
+                    error!("Recorder stopped: {}", e);
+                    if let Ok(mut slot) = writer_error.lock() {
+                        *slot = Some(e.to_string());
+                    }
+                }
+                result
+            })?;
+
+        info!("Recording {:?} to {}", config.channels, config.directory.display());
+        let recorder = Recorder {
+            config,
+            tx,
+            dropped : Arc::new(AtomicU64::new(0)),
+            stopped : Arc::new(AtomicBool::new(false)),
+            error
+        };
+        Ok((recorder, handle))
+    }
+
+    pub fn record(&self, response: &WsResponse, received: Timestamp) {
+        let (channel, write_ts) = match response {
+            WsResponse::SubscribeResponse { channel, write_ts, .. } if self.config.records(channel) => (channel, write_ts),
+            _ => return
+        };
+
+        let record = RecordedMessage { channel : channel.clone(), exchange_ts : *write_ts, received, message : response.clone() };
+        match self.tx.try_send(record) {
+            Ok(_) => {},
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                if dropped % 1000 == 1 {
+                    warn!("Recorder queue full, {} messages dropped so far", dropped);
+                }
+            },
+            Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                if !self.stopped.swap(true, Ordering::Relaxed) {
+                    let reason = self.error().unwrap_or_else(|| "the writer thread exited".to_string());
+                    error!("Recorder stopped, messages are no longer recorded: {}", reason);
+                }
+            }
+        }
+    }
+
+    // Messages dropped because the writer fell behind or stopped
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    // The error the writer stopped on, if it did
+    pub fn error(&self) -> Option<String> {
+        self.error.lock().ok().and_then(|error| error.clone())
+    }
+}
+
+trait RecordSink {
+    fn write(&mut self, record: &RecordedMessage) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct NdjsonSink {
+    encoder : GzEncoder<BufWriter<File>>,
+    pending : usize
+}
+
+impl RecordSink for NdjsonSink {
+    fn write(&mut self, record: &RecordedMessage) -> Result<()> {
+        serde_json::to_writer(&mut self.encoder, record)?;
+        self.encoder.write_all(b"\n")?;
+        self.pending += 1;
+        if self.pending >= FLUSH_EVERY {
+            self.encoder.flush()?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.encoder.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_sink {
+    use std::{fs::File, sync::Arc};
+    use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+    use eyre::Result;
+    use super::{RecordSink, RecordedMessage};
+
+    // Rows buffered before they are written as a record batch
+    const BATCH_SIZE: usize = 10_000;
+
+    pub(super) struct ParquetSink {
+        schema : SchemaRef,
+        writer : ArrowWriter<File>,
+        rows : Vec<(String, Option<i64>, i64, String)>
+    }
+
+    impl ParquetSink {
+        pub(super) fn new(file: File) -> Result<ParquetSink> {
+            let schema = Arc::new(Schema::new(vec![
+                Field::new("channel", DataType::Utf8, false),
+                Field::new("exchange_ts", DataType::Int64, true),
+                Field::new("received", DataType::Int64, false),
+                Field::new("message", DataType::Utf8, false)
+            ]));
+            let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+            let writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+            Ok(ParquetSink { schema, writer, rows : Vec::new() })
+        }
+
+        fn write_batch(&mut self) -> Result<()> {
+            if self.rows.is_empty() {
+                return Ok(())
+            }
+            let rows = std::mem::take(&mut self.rows);
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.0.as_str()))),
+                Arc::new(Int64Array::from_iter(rows.iter().map(|r| r.1))),
+                Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.2))),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.3.as_str())))
+            ];
+            self.writer.write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+            Ok(())
+        }
+    }
+
+    impl RecordSink for ParquetSink {
+        fn write(&mut self, record: &RecordedMessage) -> Result<()> {
+            self.rows.push((
+                record.channel.clone(),
+                record.exchange_ts.and_then(|ts| ts.timestamp_nanos_opt()),
+                record.received.timestamp_nanos_opt().unwrap_or_default(),
+                serde_json::to_string(&record.message)?
+            ));
+            if self.rows.len() >= BATCH_SIZE {
+                self.write_batch()?;
+            }
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> Result<()> {
+            self.write_batch()?;
+            self.writer.close()?;
+            Ok(())
+        }
+    }
+}
+
+// Files are named after the UTC hour of the messages they hold. A file left by an earlier run for
+// the same hour is kept and a numbered one is written next to it.
+fn hourly_path(directory: &Path, hour: &str, format: RecordFormat) -> PathBuf {
+    let mut path = directory.join(format!("{}.{}", hour, format.extension()));
+    let mut n = 1;
+    while path.exists() {
+        path = directory.join(format!("{}.{}.{}", hour, n, format.extension()));
+        n += 1;
+    }
+    path
+}
+
+fn open_sink(path: &Path, format: RecordFormat) -> Result<Box<dyn RecordSink>> {
+    let file = File::create(path)?;
+    match format {
+        RecordFormat::Ndjson => Ok(Box::new(NdjsonSink { encoder : GzEncoder::new(BufWriter::new(file), Compression::default()), pending : 0 })),
+        #[cfg(feature = "parquet")]
+        RecordFormat::Parquet => Ok(Box::new(parquet_sink::ParquetSink::new(file)?))
+    }
+}
+
+fn write_records(rx: &Receiver<RecordedMessage>, config: &RecorderConfig) -> Result<()> {
+    let mut current: Option<(String, Box<dyn RecordSink>)> = None;
+
+    for record in rx.iter() {
+        let hour = record.received.format("%Y%m%d-%H").to_string();
+        let sink = match current.take() {
+            Some((current_hour, sink)) if current_hour == hour => sink,
+            previous => {
+                if let Some((_, sink)) = previous {
+                    sink.finish()?;
+                }
+                let path = hourly_path(&config.directory, &hour, config.format);
+                info!("Recording to {}", path.display());
+                open_sink(&path, config.format)?
+            }
+        };
+        let (_, sink) = current.insert((hour, sink));
+        sink.write(&record)?;
+    }
+
+    if let Some((_, sink)) = current {
+        sink.finish()?;
+    }
+    Ok(())
+}
+
+// Reads back a file written in the Ndjson format
+pub fn read_ndjson(path: impl AsRef<Path>) -> Result<Vec<RecordedMessage>> {
+    let path = path.as_ref();
+    let reader = BufReader::new(MultiGzDecoder::new(File::open(path)?));
+    reader.lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(l) if l.is_empty()))
+        .map(|(n, line)| {
+            let line = line?;
+            serde_json::from_str(&line).map_err(|e| eyre!("{}:{}: {}", path.display(), n + 1, e))
+        })
+        .collect()
+}
+
+impl AevoClient {
+    // Records the configured channels from read_messages or read_raw_messages
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use alloy::{hex::ToHexExt, signers::local::PrivateKeySigner};
+use chrono::{Duration, Utc};
+use eyre::{eyre, Result};
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use crate::{
+    aevo::{AevoClient, ClientCredentials},
+    credentials::Secret,
+    rate_limit::RateLimitClass,
+    rest::{RestResponse, SigningKeyInfo, SuccessData},
+    signature::sign_register,
+    types::Timestamp
+};
+
+// Lifetime of a registered signing key when the caller has no preference
+pub const DEFAULT_KEY_LIFETIME_DAYS: i64 = 90;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RestRegister {
+    pub account : String,
+    pub signing_key : String,
+    // Seconds since the epoch
+    pub expiry : String,
+    pub account_signature : String,
+    pub signing_key_signature : String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub referral_code : Option<String>
+}
+
+// The api key is created along with the signing key
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RegisterData {
+    pub api_key : String,
+    pub api_secret : Secret,
+    #[serde(default)]
+    pub signing_keys : Vec<SigningKeyInfo>
+}
+
+// Hex private key of a signer, as read by ClientCredentials
+pub fn signer_key(signer: &PrivateKeySigner) -> Secret {
+    Secret::new(format!("0x{}", signer.to_bytes().encode_hex()))
+}
+
+fn parse_signer(key: &Secret, name: &str) -> Result<PrivateKeySigner> {
+    key.expose().parse().map_err(|_| eyre!("Invalid {}", name))
+}
+
+impl AevoClient {
+    // Registers signing_key for the wallet until expiry. Needs no api key, so an account can be
+    // onboarded from a client made without credentials.
+    pub async fn rest_register(
+        &self,
+        wallet_private_key: &Secret,
+        signing_key: &Secret,
+        expiry: Timestamp,
+        referral_code: Option<String>
+    ) -> Result<RestResponse> {
+        let wallet = parse_signer(wallet_private_key, "wallet private key")?;
+        let signer = parse_signer(signing_key, "signing key")?;
+        let (account_signature, signing_key_signature) = sign_register(&self.env, &wallet, &signer, expiry.timestamp() as u64).await?;
+
+        let body = RestRegister {
+            account : wallet.address().to_string(),
+            signing_key : signer.address().to_string(),
+            expiry : expiry.timestamp().to_string(),
+            account_signature,
+            signing_key_signature,
+            referral_code
+        };
+        info!("Registering signing key {} for {} until {}", body.signing_key, body.account, expiry);
+
+        let request = self.client
+            .post(format!("{}/register", self.env.get_config().rest_url))
+            .json(&body);
+        let response = self.send_rest(RateLimitClass::Private, request).await?;
+        let data = response.json::<RegisterData>().await?;
+        Ok(RestResponse::Register(data))
+    }
+
+    // Generates a signing key, registers it for lifetime from now and returns credentials using it
+    // and the api key created with it
+    pub async fn register_signing_key(
+        &self,
+        wallet_private_key: &Secret,
+        lifetime: Duration,
+        referral_code: Option<String>
+    ) -> Result<ClientCredentials> {
+        let wallet = parse_signer(wallet_private_key, "wallet private key")?;
+        let signing_key = signer_key(&PrivateKeySigner::random());
+
+        match self.rest_register(wallet_private_key, &signing_key, Utc::now() + lifetime, referral_code).await? {
+            RestResponse::Register(data) => Ok(ClientCredentials {
+                signing_key,
+                wallet_address : wallet.address().to_string(),
+                wallet_private_key : Some(wallet_private_key.clone()),
+                api_key : data.api_key,
+                api_secret : data.api_secret
+            }),
+            response => Err(eyre!("Unexpected register response: {:?}", response))
+        }
+    }
+
+    pub async fn rest_get_signing_keys(&self) -> Result<Vec<SigningKeyInfo>> {
+        match self.rest_get_account().await? {
+            RestResponse::GetAccount(account) => Ok(account.signing_keys),
+            response => Err(eyre!("Unexpected account response: {:?}", response))
+        }
+    }
+
+    // Revokes a signing key by its address. Orders it signed stay open.
+    pub async fn rest_delete_signing_key(&self, signing_key: &str) -> Result<RestResponse> {
+        info!("Revoking signing key {}", signing_key);
+        if let Some(credentials) = &self.credentials {
+            let mut body = HashMap::<String, String>::new();
+            body.insert("signing_key".to_string(), signing_key.to_string());
+
+            let request = self.client
+                .delete(format!("{}/signing-key", self.env.get_config().rest_url))
+                .json(&body)
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?;
+            let data = response.json::<SuccessData>().await?;
+            Ok(RestResponse::DeleteSigningKey(data))
+        } else {
+            Err(eyre!("Api key and/or secret are not established"))
+        }
+    }
+
+    pub async fn rest_delete_api_key(&self, api_key: &str) -> Result<RestResponse> {
+        info!("Revoking api key {}", api_key);
+        if let Some(credentials) = &self.credentials {
+            let mut body = HashMap::<String, String>::new();
+            body.insert("api_key".to_string(), api_key.to_string());
+
+            let request = self.client
+                .delete(format!("{}/api-key", self.env.get_config().rest_url))
+                .json(&body)
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?;
+            let data = response.json::<SuccessData>().await?;
+            Ok(RestResponse::DeleteApiKey(data))
+        } else {
+            Err(eyre!("Api key and/or secret are not established"))
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::{AtomicU64, Ordering}, Mutex},
+    time::Duration
+};
+use eyre::{eyre, Result};
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+use crate::{
+    aevo::AevoClient,
+    ws_structs::{WsRequest, WsRequestData, WsResponse}
+};
+
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Ids handed out for requests that wait on their reply start high so they do not collide with ids
+// chosen by callers of create_order and edit_order
+const FIRST_REQUEST_ID: u64 = 1 << 32;
+
+// Routes websocket replies back to the request that carried the same id. Replies are only
+// delivered while read_messages or read_raw_messages is running.
+#[derive(Debug)]
+pub struct PendingReplies {
+    next_id : AtomicU64,
+    waiters : Mutex<HashMap<u64, oneshot::Sender<WsResponse>>>
+}
+
+impl Default for PendingReplies {
+    fn default() -> PendingReplies {
+        PendingReplies { next_id : AtomicU64::new(FIRST_REQUEST_ID), waiters : Mutex::new(HashMap::new()) }
+    }
+}
+
+impl PendingReplies {
+    pub fn new() -> PendingReplies {
+        PendingReplies::default()
+    }
+
+    pub fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn register(&self, id: u64) -> oneshot::Receiver<WsResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.waiters.lock().unwrap().insert(id, tx);
+        rx
+    }
+
+    pub fn forget(&self, id: u64) {
+        self.waiters.lock().unwrap().remove(&id);
+    }
+
+    // Hands the reply to its waiter. Returns false if nobody was waiting on the id.
+    pub fn resolve(&self, response: &WsResponse) -> bool {
+        let id = match response {
+            WsResponse::PublishResponse { id : Some(id), .. } | WsResponse::ErrorResponse { id : Some(id), .. } => *id,
+            _ => return false
+        };
+
+        match self.waiters.lock().unwrap().remove(&id) {
+            Some(tx) => tx.send(response.clone()).is_ok(),
+            None => false
+        }
+    }
+}
+
+impl AevoClient {
+    // Sends a request and waits for the reply with the same id. Error replies are returned as Err.
+    pub async fn request(&self, op: &str, data: WsRequestData, timeout: Duration) -> Result<WsResponse> {
+        let id = self.replies.next_id();
+        let rx = self.replies.register(id);
+
+        let request = WsRequest { op : op.to_string(), data, id : Some(id) };
+        let msg = Message::from(serde_json::to_string(&request)?);
+        if let Err(e) = self.send(&msg).await {
+            self.replies.forget(id);
+            return Err(e)
+        }
+
+        let reply = tokio::time::timeout(timeout, rx).await;
+        self.replies.forget(id);
+        match reply {
+            Ok(Ok(WsResponse::ErrorResponse { error, .. })) => Err(eyre!("{} failed: {}", op, error)),
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(eyre!("Reply to {} was dropped", op)),
+            Err(_) => Err(eyre!("No reply to {} within {:?}", op, timeout))
+        }
+    }
+}
@@ -1,9 +1,17 @@
-use crate::aevo::{AevoClient, ClientCredentials, AMOUNT_DECIMALS, PRICE_DECIMALS};
+use crate::aevo::{to_fixed, AevoClient, ClientCredentials, AMOUNT_DECIMALS, PRICE_DECIMALS};
+use crate::batch::CancelFilter;
+use crate::rate_limit::RateLimitClass;
+use crate::paper::PaperOrderParams;
+use crate::risk::RiskReservation;
+use crate::registration::RegisterData;
+use crate::types::{Decimal, NanosStr, Timestamp};
+use crate::ws_structs::Position;
 use core::time;
 use std::collections::HashMap;
 use alloy::primitives::U256; 
 use log::{info, debug, error};
 use serde_derive::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 use eyre::{eyre, Result};
 use chrono::prelude::*;
 
@@ -20,6 +28,12 @@ pub enum RestResponse {
     CreateOrder (OrderData),
     EditOrder (OrderData), 
     Withdraw (WithdrawData), 
+    GetMmp (Vec<MmpData>), 
+    SetMmp (SuccessData), 
+    ResetMmp (SuccessData), 
+    Register (RegisterData), 
+    DeleteSigningKey (SuccessData), 
+    DeleteApiKey (SuccessData), 
     Error(ErrorData)
 }
 
@@ -28,16 +42,20 @@ pub struct ErrorData {
     pub error : String
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct WithdrawData {
-    pub timestamp : String, 
-    pub price : String
+    #[serde_as(as = "NanosStr")]
+    pub timestamp : Timestamp, 
+    pub price : Decimal
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct GetIndexData {
-    pub timestamp : String, 
-    pub price : String
+    #[serde_as(as = "NanosStr")]
+    pub timestamp : Timestamp, 
+    pub price : Decimal
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -46,14 +64,14 @@ pub struct GetAccountData {
     pub username : String, 
     pub account_type : String, 
     pub portfolio : bool, 
-    pub equity : String, 
-    pub balance : String, 
-    pub credit : String, 
+    pub equity : Decimal, 
+    pub balance : Decimal, 
+    pub credit : Decimal, 
     pub credited : bool, 
     pub collaterals : Vec<CollateralInfo>, 
-    pub available_balance : String, 
-    pub initial_margin : String, 
-    pub maintenance_margin : String, 
+    pub available_balance : Decimal, 
+    pub initial_margin : Decimal, 
+    pub maintenance_margin : Decimal, 
     pub email_address : String, 
     pub in_liquidation : bool, 
     pub referral_bonus : f64, 
@@ -61,7 +79,7 @@ pub struct GetAccountData {
     pub referrer : Option<String>, 
     pub intercom_hash : String, 
     pub permissions : Option<Vec<String>>,
-    pub positions : Vec<String>, //should be position type
+    pub positions : Vec<Position>, 
     pub signing_keys : Vec<SigningKeyInfo>, 
     pub api_keys : Vec<ApiKeyInfo>, 
     pub fee_structures : Vec<FeeStructureInfo>, 
@@ -72,16 +90,60 @@ pub struct GetAccountData {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct GetPortfolioData {
-    pub balance : String, 
-    pub pnl : String, 
-    pub realized_pnl : String, 
-    pub profit_factor : String, 
-    pub win_rate : String, 
-    pub sharpe_ratio : String, 
+    pub balance : Decimal, 
+    pub pnl : Decimal, 
+    pub realized_pnl : Decimal, 
+    pub profit_factor : Decimal, 
+    pub win_rate : Decimal, 
+    pub sharpe_ratio : Decimal, 
     pub greeks : Vec<PortfolioGreeks>, 
     pub user_margin : UsedMarginInfo
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SuccessData {
+    pub success : bool
+}
+
+// Market maker protection settings and state of one asset. Interval and frozen are in milliseconds.
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MmpData {
+    pub asset : String, 
+    pub interval : u64, 
+    pub frozen : u64, 
+    #[serde_as(as = "Option<NanosStr>")]
+    #[serde(default)]
+    pub frozen_end_time : Option<Timestamp>, 
+    pub amount_limit : Decimal, 
+    pub delta_limit : Decimal, 
+    pub vega_limit : Decimal, 
+    #[serde(default)]
+    pub amount_change : Option<Decimal>, 
+    #[serde(default)]
+    pub delta_change : Option<Decimal>, 
+    #[serde(default)]
+    pub vega_change : Option<Decimal>
+}
+
+impl MmpData {
+    pub fn is_frozen(&self, now: Timestamp) -> bool {
+        self.frozen_end_time.is_some_and(|end| end > now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MmpConfig {
+    pub asset : String, 
+    // Window in milliseconds over which fills are summed, 0 disables MMP
+    pub interval : u64, 
+    // Milliseconds the account stays frozen once MMP triggers, 0 until reset
+    pub frozen : u64, 
+    pub amount_limit : Decimal, 
+    pub delta_limit : Decimal, 
+    pub vega_limit : Decimal
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct DeleteOrdersAllData {
     pub success : bool, 
@@ -104,6 +166,23 @@ pub struct RestWithdraw {
     pub data : Option<String>
 }
 
+// The arguments of create_order_rest. Edits name the order they replace so it is not counted
+// against the risk limits twice.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RestOrderParams<'a> {
+    instrument_id : u64,
+    is_buy : bool,
+    limit_price : Option<f64>,
+    quantity : f64,
+    post_only : Option<bool>,
+    reduce_only : Option<bool>,
+    close_position : Option<bool>,
+    trigger : Option<String>,
+    stop : Option<String>,
+    time_in_force : Option<String>,
+    replaces : Option<&'a str>
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct RestOrder {
     pub maker : String, 
@@ -122,140 +201,156 @@ pub struct RestOrder {
     pub time_in_force : String
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct OrderData {
     pub order_id : String, 
     pub account : String, 
-    pub instrument_id : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
     pub instrument_name : String, 
     pub instrument_type : String, 
     pub order_type : String, 
     pub side : String, // buy  or sell
-    pub amount : String, 
-    pub price : String, 
-    pub avg_price : Option<String>, 
-    pub filled : String, 
+    pub amount : Decimal, 
+    pub price : Decimal, 
+    pub avg_price : Option<Decimal>, 
+    pub filled : Decimal, 
     pub order_status : String,
     pub post_only : Option<bool>, 
     pub reduce_only : Option<bool>, 
-    pub initial_margin : Option<String>, 
+    pub initial_margin : Option<Decimal>, 
     pub option_type : Option<String>, 
-    pub iv : Option<String>, 
-    pub expiry : Option<String>, 
-    pub strike : Option<String>, 
-    pub created_timestamp : Option<String>, 
-    pub timestamp : String, 
+    pub iv : Option<Decimal>, 
+    #[serde_as(as = "Option<NanosStr>")]
+    pub expiry : Option<Timestamp>, 
+    pub strike : Option<Decimal>, 
+    #[serde_as(as = "Option<NanosStr>")]
+    pub created_timestamp : Option<Timestamp>, 
+    #[serde_as(as = "NanosStr")]
+    pub timestamp : Timestamp, 
     pub system_type : String, 
     pub time_in_force : Option<String>, 
     pub stop : Option<String>, 
-    pub trigger : Option<String>, 
+    pub trigger : Option<Decimal>, 
     pub close_position : Option<bool>, 
     pub partial_position : Option<bool>,
-    pub isolated_margin : Option<String>, 
+    pub isolated_margin : Option<Decimal>, 
     pub parent_order_id : Option<String>,
     pub self_trade_prevention : Option<String>
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 #[serde(untagged)]
 pub enum MarketInfo {
     Perp {
-        instrument_id : String, 
+        #[serde_as(as = "DisplayFromStr")]
+        instrument_id : u64, 
         instrument_name: String, 
         instrument_type : String, 
         underlying_asset : String, 
         quote_asset : String, 
-        price_step : String, 
-        amount_step : String, 
-        min_order_value : String, 
-        max_order_value : String, 
-        max_notional_value : String, 
-        mark_price : String, 
-        index_price : String, 
+        price_step : Decimal, 
+        amount_step : Decimal, 
+        min_order_value : Decimal, 
+        max_order_value : Decimal, 
+        max_notional_value : Decimal, 
+        mark_price : Decimal, 
+        index_price : Decimal, 
         is_active : bool, 
-        max_leverage : String
+        max_leverage : Decimal
     }, 
     Option {
-        instrument_id : String, 
+        #[serde_as(as = "DisplayFromStr")]
+        instrument_id : u64, 
         instrument_name : String, 
         instrument_type : String, 
         underlying_asset : String, 
         quote_asset : String, 
-        price_step : String, 
-        amount_step : String, 
-        min_order_value : String, 
-        max_order_value : String, 
-        max_notional_value : String, 
-        mark_price : String, 
-        forward_price : String, 
-        index_price : String, 
+        price_step : Decimal, 
+        amount_step : Decimal, 
+        min_order_value : Decimal, 
+        max_order_value : Decimal, 
+        max_notional_value : Decimal, 
+        mark_price : Decimal, 
+        forward_price : Decimal, 
+        index_price : Decimal, 
         is_active : bool, 
         option_type : String, 
-        expiry : String, 
-        strike : String, 
+        #[serde_as(as = "NanosStr")]
+        expiry : Timestamp, 
+        strike : Decimal, 
         greeks : Greeks
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Greeks {
-    pub delta : String, 
-    pub theta : String, 
-    pub  gamma : String, 
-    pub rho : String, 
-    pub vega : String, 
-    pub iv : String
+    pub delta : Decimal, 
+    pub theta : Decimal, 
+    pub  gamma : Decimal, 
+    pub rho : Decimal, 
+    pub vega : Decimal, 
+    pub iv : Decimal
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct PortfolioGreeks {
     pub asset : String, 
-    pub delta : String, 
-    pub theta : String, 
-    pub gamma : String, 
-    pub rho : String, 
-    pub vega : String,
+    pub delta : Decimal, 
+    pub theta : Decimal, 
+    pub gamma : Decimal, 
+    pub rho : Decimal, 
+    pub vega : Decimal,
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct SigningKeyInfo {
     pub signing_key : String, 
-    pub expiry : String, 
-    pub created_timestamp : String
+    #[serde_as(as = "NanosStr")]
+    pub expiry : Timestamp, 
+    #[serde_as(as = "NanosStr")]
+    pub created_timestamp : Timestamp
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ApiKeyInfo {
     pub api_key : String, 
     pub read_only : bool, 
-    pub created_timestamp : String
+    #[serde_as(as = "NanosStr")]
+    pub created_timestamp : Timestamp
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct FeeStructureInfo {
     pub asset : String, 
     pub instrument_type : String, 
-    pub taker_fee : String, 
-    pub maker_fee : String
+    pub taker_fee : Decimal, 
+    pub maker_fee : Decimal
 }
 
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct LeverageInfo {
-    pub instrument_id : String, 
-    pub leverage : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
+    pub leverage : Decimal, 
     pub margin_type : String  // Cross or Margin 
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct UsedMarginInfo {
-    pub used : String, 
-    pub balance : String
+    pub used : Decimal, 
+    pub balance : Decimal
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct ManualWithdrawalInfo {
     pub account : String, 
-    pub amount : String, 
+    pub amount : Decimal, 
     pub chain_id : String, 
     pub collateral : String, 
     pub withdrawal_id : String, 
@@ -266,38 +361,42 @@ pub struct ManualWithdrawalInfo {
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct CollateralInfo {
     pub collateral_asset : String, 
-    pub balance: String, 
-    pub available_balance: String, 
-    pub withdrawable_balance : String, 
-    pub margin_value : String, 
-    pub collateral_value : String, 
+    pub balance: Decimal, 
+    pub available_balance: Decimal, 
+    pub withdrawable_balance : Decimal, 
+    pub margin_value : Decimal, 
+    pub collateral_value : Decimal, 
     pub collateral_yield_bearing : bool
 }
 
 impl AevoClient {
     pub async fn get_index(&self, asset: String) -> Result<RestResponse> {
-        let response = self.client
-            .get(format!("{}/index?asset={}", self.env.get_config().rest_url, asset))
-            .send().await?; 
+        let request = self.client
+            .get(format!("{}/index?asset={}", self.env.get_config().rest_url, asset));
+        let response = self.send_with_retry(RateLimitClass::Public, request).await?; 
         let data = response.json::<GetIndexData>().await?;
         Ok(RestResponse::GetIndex(data))
     }   
 
     pub async fn get_markets(&self, asset: String) -> Result<RestResponse> {
-        let response = self.client.get(format!("{}/markets?asset={}", self.env.get_config().rest_url, asset)).send().await?; 
+        let response = self.send_with_retry(RateLimitClass::Public, self.client.get(format!("{}/markets?asset={}", self.env.get_config().rest_url, asset))).await?; 
         let data = response.json::<Vec<MarketInfo>>().await?;
         Ok(RestResponse::GetMarkets(data))
     }
 
     pub async fn rest_cancel_order(&self, order_id : String) -> Result<RestResponse> {
         info!("Cancelling order {}", order_id); 
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let response = self.client
+        if self.paper.is_some() {
+            self.paper_cancel_order(&order_id).await?; 
+            return Ok(RestResponse::DeleteOrder(DeleteOrderData { order_id }))
+        }
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
                 .delete(format!("{}/orders/{}", self.env.get_config().rest_url, order_id))
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Order, request).await?; 
             let data = response.json::<DeleteOrderData>().await?;
+            self.orders.lock().await.mark_cancelled(&data.order_id); 
             Ok(RestResponse::DeleteOrder(data))
         } else {
             Err(eyre!("Api key and/or secret are not established"))
@@ -306,12 +405,11 @@ impl AevoClient {
 
     pub async fn rest_get_account(&self) -> Result<RestResponse> {
         info!("Getting account info"); 
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let response = self.client
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
                 .get(format!("{}/account", self.env.get_config().rest_url))
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?; 
             let data = response.json::<GetAccountData>().await?;
             Ok(RestResponse::GetAccount(data))
         } else {
@@ -321,12 +419,11 @@ impl AevoClient {
 
     pub async fn rest_get_portfolio(&self) -> Result<RestResponse> {
         info!("Getting portfolio info");
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let response = self.client
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
                 .get(format!("{}/portfolio", self.env.get_config().rest_url))
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?;  
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?;  
 
             let data = response.json::<GetPortfolioData>().await?;
             Ok(RestResponse::GetPortfolio(data))
@@ -335,14 +432,66 @@ impl AevoClient {
         }
     }
 
+    pub async fn rest_get_mmp(&self) -> Result<RestResponse> {
+        info!("Getting MMP state");
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
+                .get(format!("{}/mmp", self.env.get_config().rest_url))
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?; 
+            let data = response.json::<Vec<MmpData>>().await?;
+            Ok(RestResponse::GetMmp(data))
+        } else {
+            Err(eyre!("Api key and/or secret are not established"))
+        }
+    }
+
+    pub async fn rest_set_mmp(&self, config: &MmpConfig) -> Result<RestResponse> {
+        info!("Setting MMP for {}: {:?}", config.asset, config); 
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
+                .post(format!("{}/mmp", self.env.get_config().rest_url))
+                .json(config)
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?; 
+            let data = response.json::<SuccessData>().await?;
+            Ok(RestResponse::SetMmp(data))
+        } else {
+            Err(eyre!("Api key and/or secret are not established"))
+        }
+    }
+
+    pub async fn rest_reset_mmp(&self, asset: String) -> Result<RestResponse> {
+        info!("Resetting MMP for {}", asset); 
+        if let Some(credentials) = &self.credentials {
+            let mut body = HashMap::<String, String>::new(); 
+            body.insert("asset".to_string(), asset.clone()); 
+
+            let request = self.client
+                .post(format!("{}/reset-mmp", self.env.get_config().rest_url))
+                .json(&body)
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?; 
+            let data = response.json::<SuccessData>().await?;
+            if data.success {
+                self.mmp.lock().await.mark_reset(&asset); 
+            }
+            Ok(RestResponse::ResetMmp(data))
+        } else {
+            Err(eyre!("Api key and/or secret are not established"))
+        }
+    }
+
     pub async fn rest_get_open_orders(&self) -> Result<RestResponse> {
         info!("Getting open orders");
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let response = self.client
+        if let Some(paper) = &self.paper {
+            return Ok(RestResponse::GetOrders(paper.exchange.lock().await.open_orders()))
+        }
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
                 .get(format!("{}/orders", self.env.get_config().rest_url))
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?; 
             info!("Response: {:?}", response); 
             let data = response.json::<Vec<OrderData>>().await?;
             Ok(RestResponse::GetOrders(data))
@@ -353,7 +502,11 @@ impl AevoClient {
 
     pub async fn rest_cancel_all_orders(&self, instrument_type: Option<String>, asset: Option<String> ) -> Result<RestResponse> {
         info!("Cancelling all orders"); 
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
+        if self.paper.is_some() {
+            let order_ids = self.paper_cancel_all_orders(&CancelFilter { instrument_type, asset, instrument : None }).await?; 
+            return Ok(RestResponse::DeleteOrdersAll(DeleteOrdersAllData { success : true, order_ids }))
+        }
+        if let Some(credentials) = &self.credentials {
             let mut body = HashMap::<String, String>::new(); 
             if let Some(i_t) = instrument_type {
                 body.insert("instrument_type".to_string(), i_t); 
@@ -363,13 +516,18 @@ impl AevoClient {
                 body.insert("asset".to_string(), a); 
             };
 
-            let response = self.client
+            let request = self.client
                 .delete(format!("{}/orders-all", self.env.get_config().rest_url))
                 .json(&body)
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Order, request).await?; 
             let data = response.json::<DeleteOrdersAllData>().await?;
+            {
+                let mut orders = self.orders.lock().await; 
+                for order_id in data.order_ids.iter() {
+                    orders.mark_cancelled(order_id); 
+                }
+            }
             Ok(RestResponse::DeleteOrdersAll(data))
         } else {
             Err(eyre!("Api key and/or secret are not established"))
@@ -390,6 +548,31 @@ impl AevoClient {
         stop: Option<String>,
         time_in_force: Option<String>
     ) -> Result<(RestOrder, String)>{
+        let params = RestOrderParams {
+            instrument_id,
+            is_buy,
+            limit_price,
+            quantity,
+            post_only,
+            reduce_only,
+            close_position,
+            trigger,
+            stop,
+            time_in_force,
+            replaces : None
+        };
+        let (data, order_id, _) = self.reserved_order_rest(params).await?; 
+        Ok((data, order_id))
+    }
+
+    // create_order_rest keeping the risk reservation, to be dropped once the order is recorded
+    async fn reserved_order_rest(&self, params: RestOrderParams<'_>) -> Result<(RestOrder, String, RiskReservation)>{
+        let RestOrderParams {
+            instrument_id, is_buy, limit_price, quantity, post_only, reduce_only, close_position, trigger, stop, time_in_force, replaces
+        } = params;
+        let (limit_price, quantity) = self.prepare_order(instrument_id, is_buy, limit_price, quantity).await?; 
+        let reservation = self.check_risk(instrument_id, is_buy, limit_price, quantity, replaces).await?; 
+
         let timestamp = Utc::now().timestamp();
         let (salt, signature, order_id) = self.sign_order(
             instrument_id, 
@@ -409,7 +592,7 @@ impl AevoClient {
             is_buy: is_buy, 
             instrument: instrument_id.to_string(), 
             limit_price : match limit_price {
-                Some(p) => (p * 10_i32.pow(PRICE_DECIMALS) as f64).floor().to_string(), 
+                Some(p) => to_fixed(p, PRICE_DECIMALS)?, 
                 None => {
                     if is_buy {
                         U256::MAX.to_string()
@@ -418,7 +601,7 @@ impl AevoClient {
                     }
                 }
             }, 
-            amount : (quantity * 10_i32.pow(AMOUNT_DECIMALS) as f64).floor().to_string(), 
+            amount : to_fixed(quantity, AMOUNT_DECIMALS)?, 
             salt : salt.to_string(), 
             signature : signature, 
             post_only : match post_only {
@@ -439,7 +622,7 @@ impl AevoClient {
             time_in_force : time_in_force.unwrap_or("GTC".to_string())
         }; 
 
-        Ok((payload, order_id))
+        Ok((payload, order_id, reservation))
     }
 
     pub async fn rest_create_order (
@@ -451,30 +634,41 @@ impl AevoClient {
         post_only: Option<bool>, 
         time_in_force: Option<String>
     ) -> Result<RestResponse>{
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let (data, order_id) = self.create_order_rest(
-                instrument_id, 
-                is_buy, 
-                Some(limit_price), 
-                quantity, 
-                post_only, 
-                None, 
-                None, 
-                None, 
-                None,
-                time_in_force
-            ).await?; 
+        if self.paper.is_some() {
+            let params = PaperOrderParams {
+                replaces : None,
+                instrument_id,
+                is_buy,
+                limit_price : Some(limit_price),
+                quantity,
+                post_only : post_only.unwrap_or(true),
+                ioc : time_in_force.as_deref() == Some("IOC")
+            };
+            let data = self.paper_rest_create_order(params).await?; 
+            return Ok(RestResponse::CreateOrder(data))
+        }
+
+        if let Some(credentials) = &self.credentials {
+            let params = RestOrderParams {
+                instrument_id,
+                is_buy,
+                limit_price : Some(limit_price),
+                quantity,
+                post_only,
+                time_in_force,
+                ..RestOrderParams::default()
+            };
+            let (data, order_id, _reservation) = self.reserved_order_rest(params).await?; 
     
             info!("Creating rest order: {:?}", data); 
     
-            let response = self.client
+            let request = self.client
                 .post(format!("{}/orders", self.env.get_config().rest_url))
                 .json(&data)
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
-            debug!("The response is {:?}", response); 
-            let data = response.json::<OrderData>().await?;
+                .headers(credentials.auth_headers()?);
+            let data = self.submit_order_rest(request, &order_id).await?;
+            debug!("The response is {:?}", data); 
+            self.orders.lock().await.apply_order_data(&data); 
             Ok(RestResponse::CreateOrder(data))
         } else {
             Err(eyre!("Api key and/or secret are not established"))
@@ -491,29 +685,45 @@ impl AevoClient {
         post_only: Option<bool>, 
         time_in_force: Option<String>
     ) -> Result<RestResponse> {
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let (data, new_order_id) = self.create_order_rest(
-                instrument_id, 
-                is_buy, 
-                Some(limit_price), 
-                quantity, 
-                post_only, 
-                None, 
-                None, 
-                None, 
-                None,
-                time_in_force
-            ).await?; 
+        if self.paper.is_some() {
+            let params = PaperOrderParams {
+                replaces : Some(order_id),
+                instrument_id,
+                is_buy,
+                limit_price : Some(limit_price),
+                quantity,
+                post_only : post_only.unwrap_or(true),
+                ioc : time_in_force.as_deref() == Some("IOC")
+            };
+            let data = self.paper_rest_create_order(params).await?; 
+            return Ok(RestResponse::EditOrder(data))
+        }
+
+        if let Some(credentials) = &self.credentials {
+            let params = RestOrderParams {
+                instrument_id,
+                is_buy,
+                limit_price : Some(limit_price),
+                quantity,
+                post_only,
+                time_in_force,
+                replaces : Some(order_id),
+                ..RestOrderParams::default()
+            };
+            let (data, new_order_id, _reservation) = self.reserved_order_rest(params).await?; 
     
             info!("Editing rest order: {:?}", data); 
     
-            let response = self.client
+            let request = self.client
                 .post(format!("{}/orders/{}", self.env.get_config().rest_url, order_id))
                 .json(&data)
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
-            let data = response.json::<OrderData>().await?;
+                .headers(credentials.auth_headers()?);
+            let data = self.submit_order_rest(request, &new_order_id).await?;
+            {
+                let mut orders = self.orders.lock().await; 
+                orders.apply_order_data(&data); 
+                orders.mark_cancelled(order_id); 
+            }
             Ok(RestResponse::EditOrder(data))
         } else {
             Err(eyre!("Api key and/or secret are not established"))
@@ -526,30 +736,39 @@ impl AevoClient {
         is_buy: bool, 
         quantity: f64
     ) -> Result<RestResponse> {
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
-            let (data, order_id) = self.create_order_rest(
-                instrument_id, 
-                is_buy, 
-                None, 
-                quantity, 
-                Some(false), 
-                None, 
-                None, 
-                None, 
-                None,
-                Some("IOC".to_string())
-            ).await?; 
+        if self.paper.is_some() {
+            let params = PaperOrderParams {
+                replaces : None,
+                instrument_id,
+                is_buy,
+                limit_price : None,
+                quantity,
+                post_only : false,
+                ioc : true
+            };
+            let data = self.paper_rest_create_order(params).await?; 
+            return Ok(RestResponse::CreateOrder(data))
+        }
+
+        if let Some(credentials) = &self.credentials {
+            let params = RestOrderParams {
+                instrument_id,
+                is_buy,
+                quantity,
+                post_only : Some(false),
+                time_in_force : Some("IOC".to_string()),
+                ..RestOrderParams::default()
+            };
+            let (data, order_id, _reservation) = self.reserved_order_rest(params).await?; 
     
             info!("Creating rest market order: {:?}", data); 
     
-            let response = self.client
+            let request = self.client
                 .post(format!("{}/orders", self.env.get_config().rest_url))
                 .json(&data)
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
-            
-            let data = response.json::<OrderData>().await?;
+                .headers(credentials.auth_headers()?);
+            let data = self.submit_order_rest(request, &order_id).await?;
+            self.orders.lock().await.apply_order_data(&data); 
             Ok(RestResponse::CreateOrder(data))
         } else {
             Err(eyre!("Api key and/or secret are not established"))
@@ -563,7 +782,7 @@ impl AevoClient {
         to: Option<String>, 
         data: Option<U256>,
     ) -> Result<RestResponse> {
-        if let Some(ClientCredentials{api_key, api_secret, ..}) = &self.credentials {
+        if let Some(credentials) = &self.credentials {
             let collateral = match collateral {
                 Some(val) => val, 
                 None => self.env.get_addresses().l1_usdc,
@@ -578,12 +797,11 @@ impl AevoClient {
     
             info!("Withdrawing {}", withdraw_id);
     
-            let response = self.client
+            let request = self.client
                 .post(format!("{}/withdraw", self.env.get_config().rest_url))
                 .json(&data)
-                .header("AEVO-KEY", api_key)
-                .header("AEVO-SECRET", api_secret)
-                .send().await?; 
+                .headers(credentials.auth_headers()?);
+            let response = self.send_rest(RateLimitClass::Private, request).await?; 
             
             let data = response.json::<WithdrawData>().await?;
     
@@ -617,7 +835,7 @@ impl AevoClient {
             account : wallet_address, 
             collateral: collateral, 
             to: to, 
-            amount : (amount * 10_i32.pow(AMOUNT_DECIMALS) as f64).floor().to_string(), 
+            amount : to_fixed(amount, AMOUNT_DECIMALS)?, 
             salt: salt.to_string(), 
             signature: signature, 
             data: match data {
@@ -0,0 +1,142 @@
+use std::time::Duration;
+use log::{info, warn};
+use eyre::{eyre, Result};
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use crate::{aevo::AevoClient, rate_limit::RateLimitClass, rest::OrderData};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // Retries after the first attempt, 0 disables retrying
+    pub max_retries : u32,
+    pub initial_backoff : Duration,
+    pub max_backoff : Duration,
+    pub multiplier : f64,
+    // Fraction of the backoff randomized so clients do not retry in lockstep
+    pub jitter : f64
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries : 3,
+            initial_backoff : Duration::from_millis(200),
+            max_backoff : Duration::from_secs(5),
+            multiplier : 2.0,
+            jitter : 0.2
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn none() -> RetryPolicy {
+        RetryPolicy { max_retries : 0, ..RetryPolicy::default() }
+    }
+
+    // Backoff before retry number `attempt`, starting at 0, without jitter
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+    }
+
+    fn jittered_backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt).as_secs_f64();
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return Duration::from_secs_f64(backoff)
+        }
+        Duration::from_secs_f64(backoff * rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+    }
+}
+
+// Connection failures and timeouts, where the request may be retried safely if it is idempotent
+pub fn is_transient_error(error: &eyre::Report) -> bool {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+        None => false
+    }
+}
+
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+}
+
+impl AevoClient {
+    // Sends an idempotent request, retrying transient failures and 5xx responses with backoff.
+    // Used for reads and for cancels, which are idempotent by order id.
+    pub(crate) async fn send_with_retry(&self, class: RateLimitClass, request: RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let retry = request.try_clone()
+                .ok_or_else(|| eyre!("Request can not be retried"))?;
+
+            let failure = match self.send_rest(class, retry).await {
+                Ok(response) if !is_transient_status(response.status()) => return Ok(response),
+                Ok(response) if attempt >= self.retry_policy.max_retries => return Ok(response),
+                Ok(response) => format!("status {}", response.status()),
+                Err(e) if attempt < self.retry_policy.max_retries && is_transient_error(&e) => e.to_string(),
+                Err(e) => return Err(e)
+            };
+
+            let backoff = self.retry_policy.jittered_backoff(attempt);
+            warn!("{:?} request failed with {}, retrying in {:?}", class, failure, backoff);
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    // Looks an order up by its hash. Returns None if the exchange does not know the order.
+    pub async fn rest_get_order(&self, order_id: &str) -> Result<Option<OrderData>> {
+        if let Some(paper) = &self.paper {
+            return Ok(paper.exchange.lock().await.order_data(order_id))
+        }
+        if let Some(credentials) = &self.credentials {
+            let request = self.client
+                .get(format!("{}/orders/{}", self.env.get_config().rest_url, order_id))
+                .headers(credentials.auth_headers()?);
+            let response = self.send_with_retry(RateLimitClass::Private, request).await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None)
+            }
+            Ok(Some(response.json::<OrderData>().await?))
+        } else {
+            Err(eyre!("Api key and/or secret are not established"))
+        }
+    }
+
+    // Order creation is not blindly retried. The order id is the hash of the signed order, so after
+    // a transient failure the exchange is asked whether the order arrived before resending the same
+    // signed payload, which the exchange would reject as a duplicate anyway.
+    pub(crate) async fn submit_order_rest(&self, request: RequestBuilder, order_id: &str) -> Result<OrderData> {
+        let mut attempt = 0;
+        loop {
+            let retry = request.try_clone()
+                .ok_or_else(|| eyre!("Request can not be retried"))?;
+
+            let failure = match self.send_rest(RateLimitClass::Order, retry).await {
+                Ok(response) if !is_transient_status(response.status()) => return Ok(response.json::<OrderData>().await?),
+                Ok(response) => eyre!("Order {} failed with status {}", order_id, response.status()),
+                Err(e) if is_transient_error(&e) => e,
+                Err(e) => return Err(e)
+            };
+
+            match self.rest_get_order(order_id).await {
+                Ok(Some(order)) => {
+                    info!("Order {} reached the exchange despite {}", order_id, failure);
+                    return Ok(order)
+                },
+                Ok(None) => {},
+                Err(e) => warn!("Problem looking up order {}: {}", order_id, e)
+            }
+
+            if attempt >= self.retry_policy.max_retries {
+                return Err(failure)
+            }
+
+            let backoff = self.retry_policy.jittered_backoff(attempt);
+            warn!("Order {} failed with {}, resubmitting in {:?}", order_id, failure, backoff);
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+}
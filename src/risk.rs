@@ -0,0 +1,322 @@
+use std::{collections::{HashMap, VecDeque}, fmt, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use log::warn;
+use eyre::Result;
+use crate::{
+    aevo::AevoClient,
+    instruments::{to_decimal, InstrumentName},
+    types::Decimal,
+    ws_structs::{WsResponse, WsResponseData}
+};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+// Limits left as None are not enforced
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RiskLimits {
+    pub max_order_notional : Option<Decimal>,
+    // Absolute contracts held in a single instrument including resting orders
+    pub max_position_per_instrument : Option<Decimal>,
+    // Absolute notional held across all instruments of an underlying asset
+    pub max_position_per_asset : Option<Decimal>,
+    // Maximum distance of the limit price from the mark price as a fraction, e.g. 0.05 for 5%
+    pub price_band : Option<Decimal>,
+    pub max_open_orders : Option<usize>,
+    pub max_orders_per_minute : Option<usize>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RiskRejection {
+    OrderNotional { notional : Decimal, limit : Decimal },
+    InstrumentPosition { instrument_id : u64, projected : Decimal, limit : Decimal },
+    AssetPosition { asset : String, projected : Decimal, limit : Decimal },
+    PriceBand { price : Decimal, reference : Decimal, band : Decimal },
+    OpenOrders { open : usize, limit : usize },
+    OrderRate { count : usize, limit : usize },
+    // A market order without a reference price can not be held to the notional limits or band
+    UnknownPrice { instrument_id : u64 },
+    KillSwitch { reason : String }
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskRejection::OrderNotional { notional, limit } =>
+                write!(f, "Order notional {} exceeds limit {}", notional, limit),
+            RiskRejection::InstrumentPosition { instrument_id, projected, limit } =>
+                write!(f, "Position in instrument {} would be {} above limit {}", instrument_id, projected, limit),
+            RiskRejection::AssetPosition { asset, projected, limit } =>
+                write!(f, "Notional in {} would be {} above limit {}", asset, projected, limit),
+            RiskRejection::PriceBand { price, reference, band } =>
+                write!(f, "Price {} is more than {} away from reference {}", price, band, reference),
+            RiskRejection::OpenOrders { open, limit } =>
+                write!(f, "{} open orders already at limit {}", open, limit),
+            RiskRejection::OrderRate { count, limit } =>
+                write!(f, "{} orders in the last minute already at limit {}", count, limit),
+            RiskRejection::UnknownPrice { instrument_id } =>
+                write!(f, "No reference price for market order on instrument {}, subscribe to its ticker", instrument_id),
+            RiskRejection::KillSwitch { reason } =>
+                write!(f, "Kill switch engaged: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for RiskRejection {}
+
+// Everything the risk check needs to know about an order and the account at the time it is placed
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRisk {
+    pub instrument_id : u64,
+    pub asset : Option<String>,
+    pub is_buy : bool,
+    // None for market orders
+    pub price : Option<Decimal>,
+    pub amount : Decimal,
+    // Signed position in the instrument
+    pub position : Decimal,
+    // Signed remaining amount of resting orders in the instrument
+    pub resting : Decimal,
+    // Absolute notional currently held in the asset
+    pub asset_notional : Decimal,
+    pub open_orders : usize,
+    // Edits replace a resting order so they do not add to the open order count
+    pub is_edit : bool
+}
+
+// Exposure of an order that passed the checks but is not tracked by the order manager yet
+#[derive(Debug, Clone, PartialEq)]
+struct Exposure {
+    instrument_id : u64,
+    asset : Option<String>,
+    signed_amount : Decimal,
+    notional : Decimal,
+    is_edit : bool
+}
+
+type Reservations = Arc<Mutex<HashMap<u64, Exposure>>>;
+
+// Holds an order's exposure against the limits from its risk check until the order manager knows
+// the order, so orders checked concurrently, e.g. in a batch, can not exceed the limits together.
+// Released on drop.
+#[derive(Debug)]
+pub struct RiskReservation {
+    id : u64,
+    reservations : Reservations
+}
+
+impl Drop for RiskReservation {
+    fn drop(&mut self) {
+        if let Ok(mut reservations) = self.reservations.lock() {
+            reservations.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RiskManager {
+    pub limits : RiskLimits,
+    reference_prices : HashMap<u64, Decimal>,
+    recent_orders : VecDeque<Instant>,
+    reservations : Reservations,
+    next_reservation : u64
+}
+
+impl RiskManager {
+    pub fn new(limits: RiskLimits) -> RiskManager {
+        RiskManager { limits, ..RiskManager::default() }
+    }
+
+    pub fn update_reference(&mut self, instrument_id: u64, price: Decimal) {
+        if !price.is_zero() {
+            self.reference_prices.insert(instrument_id, price);
+        }
+    }
+
+    pub fn reference_price(&self, instrument_id: u64) -> Option<Decimal> {
+        self.reference_prices.get(&instrument_id).copied()
+    }
+
+    // Keeps mark prices current from the ticker and positions channels. Market orders are rejected
+    // without one while a notional limit or price band is set.
+    pub fn apply(&mut self, response: &WsResponse) {
+        if let WsResponse::SubscribeResponse { data, .. } = response {
+            match data {
+                WsResponseData::TickerData { tickers, .. } => {
+                    for ticker in tickers {
+                        self.update_reference(ticker.instrument_id, ticker.mark.price);
+                    }
+                },
+                WsResponseData::PositionsData { positions, .. } => {
+                    for position in positions {
+                        self.update_reference(position.instrument_id, position.mark_price);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // Adds the exposure of reserved orders to what the order manager and positions report
+    fn with_reserved(&self, order: &OrderRisk) -> OrderRisk {
+        let mut order = order.clone();
+        if let Ok(reservations) = self.reservations.lock() {
+            for exposure in reservations.values() {
+                if exposure.instrument_id == order.instrument_id {
+                    order.resting += exposure.signed_amount;
+                }
+                if order.asset.is_some() && exposure.asset == order.asset {
+                    order.asset_notional += exposure.notional;
+                }
+                if !exposure.is_edit {
+                    order.open_orders += 1;
+                }
+            }
+        }
+        order
+    }
+
+    // Runs every configured check, counting reserved orders as resting, and counts the order
+    // against the rate limit when it passes. The order stays reserved until the returned
+    // reservation is dropped.
+    pub fn check(&mut self, order: &OrderRisk) -> std::result::Result<RiskReservation, RiskRejection> {
+        let order = &self.with_reserved(order);
+        let limits = &self.limits;
+        let reference = self.reference_prices.get(&order.instrument_id).copied();
+        let price = order.price.or(reference);
+        let signed_amount = if order.is_buy { order.amount } else { -order.amount };
+
+        let priced_limits = limits.max_order_notional.is_some() || limits.max_position_per_asset.is_some() || limits.price_band.is_some();
+        if price.is_none() && priced_limits {
+            return Err(RiskRejection::UnknownPrice { instrument_id : order.instrument_id })
+        }
+
+        if let (Some(limit), Some(price)) = (limits.max_order_notional, price) {
+            let notional = price * order.amount;
+            if notional > limit {
+                return Err(RiskRejection::OrderNotional { notional, limit })
+            }
+        }
+
+        if let Some(limit) = limits.max_position_per_instrument {
+            let projected = (order.position + order.resting + signed_amount).abs();
+            if projected > limit && projected > order.position.abs() {
+                return Err(RiskRejection::InstrumentPosition { instrument_id : order.instrument_id, projected, limit })
+            }
+        }
+
+        if let (Some(limit), Some(asset), Some(price)) = (limits.max_position_per_asset, &order.asset, price) {
+            let projected = order.asset_notional + price * order.amount;
+            if projected > limit {
+                return Err(RiskRejection::AssetPosition { asset : asset.clone(), projected, limit })
+            }
+        }
+
+        if let (Some(band), Some(price), Some(reference)) = (limits.price_band, order.price, reference) {
+            if ((price - reference) / reference).abs() > band {
+                return Err(RiskRejection::PriceBand { price, reference, band })
+            }
+        }
+
+        if let Some(limit) = limits.max_open_orders {
+            if !order.is_edit && order.open_orders >= limit {
+                return Err(RiskRejection::OpenOrders { open : order.open_orders, limit })
+            }
+        }
+
+        let now = Instant::now();
+        while self.recent_orders.front().is_some_and(|t| now.duration_since(*t) > RATE_WINDOW) {
+            self.recent_orders.pop_front();
+        }
+        if let Some(limit) = self.limits.max_orders_per_minute {
+            if self.recent_orders.len() >= limit {
+                return Err(RiskRejection::OrderRate { count : self.recent_orders.len(), limit })
+            }
+        }
+        self.recent_orders.push_back(now);
+
+        let exposure = Exposure {
+            instrument_id : order.instrument_id,
+            asset : order.asset.clone(),
+            signed_amount,
+            notional : price.map(|p| p * order.amount).unwrap_or_default(),
+            is_edit : order.is_edit
+        };
+        self.next_reservation += 1;
+        if let Ok(mut reservations) = self.reservations.lock() {
+            reservations.insert(self.next_reservation, exposure);
+        }
+        Ok(RiskReservation { id : self.next_reservation, reservations : self.reservations.clone() })
+    }
+}
+
+impl AevoClient {
+    pub async fn set_risk_limits(&self, limits: RiskLimits) {
+        self.risk.lock().await.limits = limits;
+    }
+
+    // Called on every order path before the order is signed. Rejections are returned as a
+    // RiskRejection inside the eyre report. Keep the reservation until the order is recorded in the
+    // order manager. The risk lock is held throughout so concurrent checks see each other.
+    pub async fn check_risk(
+        &self,
+        instrument_id: u64,
+        is_buy: bool,
+        limit_price: Option<f64>,
+        quantity: f64,
+        replaces: Option<&str>
+    ) -> Result<RiskReservation> {
+        if self.kill_switch.is_engaged() {
+            let rejection = RiskRejection::KillSwitch { reason : self.kill_switch.reason().unwrap_or_default() };
+            warn!("Order on instrument {} rejected: {}", instrument_id, rejection);
+            return Err(rejection.into())
+        }
+
+        let mut risk = self.risk.lock().await;
+        let instrument_name = self.resolve_instrument_name(instrument_id).await;
+        let asset = instrument_name.as_ref()
+            .and_then(|name| name.parse::<InstrumentName>().ok())
+            .map(|name| name.underlying);
+
+        let (position, asset_notional) = {
+            let positions = self.positions.lock().await;
+            let position = positions.get(instrument_id).map(|p| p.size).unwrap_or_default();
+            let asset_notional = positions.open_positions().iter()
+                .filter(|p| asset.is_some() && p.instrument_name.parse::<InstrumentName>().ok().map(|n| n.underlying) == asset)
+                .map(|p| p.notional())
+                .sum();
+            (position, asset_notional)
+        };
+
+        let (resting, open_orders) = {
+            let orders = self.orders.lock().await;
+            let resting = orders.open_orders_for(instrument_id).iter()
+                .filter(|o| Some(o.order_id.as_str()) != replaces)
+                .map(|o| if o.is_buy { o.remaining() } else { -o.remaining() })
+                .sum();
+            (resting, orders.open_orders().len())
+        };
+
+        let order = OrderRisk {
+            instrument_id,
+            asset,
+            is_buy,
+            price : match limit_price {
+                Some(p) => Some(to_decimal(p)?),
+                None => None
+            },
+            amount : to_decimal(quantity)?,
+            position,
+            resting,
+            asset_notional,
+            open_orders,
+            is_edit : replaces.is_some()
+        };
+
+        match risk.check(&order) {
+            Ok(reservation) => Ok(reservation),
+            Err(rejection) => {
+                warn!("Order on instrument {} rejected by risk checks: {}", instrument_id, rejection);
+                Err(rejection.into())
+            }
+        }
+    }
+}
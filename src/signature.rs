@@ -1,4 +1,4 @@
-use crate::aevo::{AevoClient, PRICE_DECIMALS, AMOUNT_DECIMALS};
+use crate::{aevo::{to_fixed, AevoClient, PRICE_DECIMALS, AMOUNT_DECIMALS}, env::ENV};
 use alloy::{hex::ToHexExt, primitives::{address, bytes, keccak256, Address, Sign, Signature, I256, U256}, signers::{local::{LocalSigner, PrivateKeySigner}, Signer}, sol}; 
 use alloy::sol_types::Eip712Domain;
 use eyre::{eyre, Result}; 
@@ -23,6 +23,47 @@ sol!{
         uint256 salt; 
         uint256 data; 
     }
+
+    struct Register {
+        address key; 
+        uint256 expiry; 
+    }
+
+    struct SignKey {
+        address account; 
+    }
+}
+
+// Signatures for registering a signing key: the wallet signs the key and its expiry in seconds, and
+// the key signs the wallet address. Returns the account and signing key signatures.
+pub async fn sign_register(
+    env: &ENV, 
+    wallet: &PrivateKeySigner, 
+    signing_key: &PrivateKeySigner, 
+    expiry: u64
+) -> Result<(String, String)> {
+    let signing_domain = env.get_config().signing_domain; 
+
+    let domain = Eip712Domain {
+        name: Some(signing_domain.name.into()), 
+        version: Some(signing_domain.version.into()), 
+        chain_id: Some(signing_domain.chain_id), 
+        verifying_contract: None, 
+        salt: None
+    };
+
+    let register = Register {
+        key: signing_key.address(), 
+        expiry: U256::from(expiry)
+    }; 
+    let sign_key = SignKey {
+        account: wallet.address()
+    }; 
+
+    let account_signature: Signature = wallet.sign_hash(&register.eip712_signing_hash(&domain)).await?;
+    let signing_key_signature: Signature = signing_key.sign_hash(&sign_key.eip712_signing_hash(&domain)).await?;
+
+    Ok((format!("0x{}", account_signature.as_bytes().encode_hex()), format!("0x{}", signing_key_signature.as_bytes().encode_hex())))
 }
 
 impl AevoClient {
@@ -37,7 +78,7 @@ impl AevoClient {
         let salt = U256::from(rand::random::<u64>());
 
         let price = match limit_price {
-            Some(p) => U256::from((p * 10_i32.pow(PRICE_DECIMALS) as f64).floor()), 
+            Some(p) => U256::from_str(&to_fixed(p, PRICE_DECIMALS)?)?, 
             None => {
                 if is_buy {
                     U256::MAX 
@@ -54,16 +95,13 @@ impl AevoClient {
             .parse()?;  
         
 
-        let signing_key = &self.credentials
-            .as_ref()
-            .ok_or_else(|| eyre!("Order sign error: Signing key not set"))?
-            .signing_key; 
+        let signing_key = self.current_signing_key()?; 
 
         let order = Order {
             maker: wallet_address, 
             isBuy: is_buy, 
             limitPrice: price, 
-            amount: U256::from((quantity * 10_i32.pow(AMOUNT_DECIMALS) as f64).floor()), 
+            amount: U256::from_str(&to_fixed(quantity, AMOUNT_DECIMALS)?)?, 
             salt: salt, 
             instrument: U256::from(instrument_id), 
             timestamp : U256::from(timestamp)
@@ -80,7 +118,7 @@ impl AevoClient {
         };
         
         let signable_bytes = order.eip712_signing_hash(&domain); 
-        let signer: PrivateKeySigner = signing_key.parse().expect("should parse private key"); 
+        let signer: PrivateKeySigner = signing_key.expose().parse().expect("should parse private key"); 
         let signature: Signature = signer.sign_hash(&signable_bytes).await?;
 
         Ok((salt, format!("0x{}",signature.as_bytes().encode_hex()), format!("0x{}", signable_bytes.encode_hex())))
@@ -95,15 +133,12 @@ impl AevoClient {
     ) -> Result<(U256, String, String)>{
         let salt = U256::from(rand::random::<u64>());
 
-        let signing_key = &self.credentials
-            .as_ref()
-            .ok_or_else(|| eyre!("Order sign error: Signing key not set"))?
-            .signing_key; 
+        let signing_key = self.current_signing_key()?; 
 
         let withdraw = Withdraw {
             collateral: collateral.parse()?, 
             to: to.parse()?, 
-            amount: U256::from((amount * 10_i32.pow(AMOUNT_DECIMALS) as f64).floor()), 
+            amount: U256::from_str(&to_fixed(amount, AMOUNT_DECIMALS)?)?, 
             salt: salt, 
             data: data
         }; 
@@ -119,7 +154,7 @@ impl AevoClient {
         };
         
         let signable_bytes = withdraw.eip712_signing_hash(&domain); 
-        let signer = PrivateKeySigner::from_str(signing_key)?; 
+        let signer = PrivateKeySigner::from_str(signing_key.expose())?; 
         let signature: Signature = signer.sign_hash(&signable_bytes).await?;
 
         Ok((salt, format!("0x{}",signature.as_bytes().encode_hex()), format!("0x{}", signable_bytes.encode_hex())))
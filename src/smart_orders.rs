@@ -0,0 +1,291 @@
+use std::{sync::Arc, time::Duration};
+use log::{info, warn, error};
+use eyre::{eyre, Result};
+use rust_decimal::prelude::ToPrimitive;
+use tokio::task::JoinHandle;
+use crate::{
+    aevo::AevoClient,
+    execution::{AlgoHandle, AlgoStatus},
+    order_manager::OrderState,
+    types::Decimal,
+    ws_structs::{WsResponse, WsResponseData}
+};
+
+// How often the control state of the handle is checked between order and market events
+const CONTROL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcebergParams {
+    pub instrument_id : u64,
+    pub is_buy : bool,
+    pub price : Decimal,
+    pub quantity : Decimal,
+    // Size shown on the book at any time
+    pub visible : Decimal,
+    pub mmp : Option<bool>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaseParams {
+    pub instrument_id : u64,
+    pub is_buy : bool,
+    pub quantity : Decimal,
+    // Furthest the order may move from the touch price seen when it started
+    pub max_slippage : Decimal,
+    pub mmp : Option<bool>
+}
+
+// A post-only child order of an algo
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChildOrder {
+    instrument_id : u64,
+    is_buy : bool,
+    price : Decimal,
+    amount : Decimal,
+    mmp : Option<bool>
+}
+
+impl IcebergParams {
+    fn child(&self, amount: Decimal) -> ChildOrder {
+        ChildOrder { instrument_id : self.instrument_id, is_buy : self.is_buy, price : self.price, amount, mmp : self.mmp }
+    }
+}
+
+impl ChaseParams {
+    fn child(&self, price: Decimal, amount: Decimal) -> ChildOrder {
+        ChildOrder { instrument_id : self.instrument_id, is_buy : self.is_buy, price, amount, mmp : self.mmp }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct WorkingOrder {
+    pub order_id : String,
+    pub price : Decimal,
+    // The order an edit is replacing until the exchange acknowledges the edit. It keeps resting when
+    // the edit is rejected.
+    pub replaces : Option<Box<WorkingOrder>>
+}
+
+// The order worked after an event for the working order or the one it replaces. A rejected edit
+// falls back to the order it was meant to replace, so no second order is placed next to it.
+pub(crate) fn follow_child(working: Option<WorkingOrder>, order_id: &str, state: OrderState) -> Option<WorkingOrder> {
+    let order = working?;
+    if order.order_id == order_id {
+        match state {
+            OrderState::Pending => Some(order),
+            OrderState::Filled | OrderState::Cancelled => None,
+            OrderState::Rejected => order.replaces.map(|replaced| *replaced),
+            OrderState::Acknowledged | OrderState::PartiallyFilled => Some(WorkingOrder { replaces : None, ..order })
+        }
+    } else if order.replaces.as_ref().is_some_and(|replaced| replaced.order_id == order_id) && state.is_terminal() {
+        // Gone either way, so a rejected edit leaves nothing resting
+        Some(WorkingOrder { replaces : None, ..order })
+    } else {
+        Some(order)
+    }
+}
+
+fn to_f64(value: Decimal) -> Result<f64> {
+    value.to_f64().ok_or_else(|| eyre!("Value {} out of range", value))
+}
+
+// True if `price` is a better price to trade at than `other` for the side
+pub fn more_aggressive(is_buy: bool, price: Decimal, other: Decimal) -> bool {
+    if is_buy { price > other } else { price < other }
+}
+
+// Price a chase order is placed at: the best bid for buys and the best ask for sells. The exchange
+// reports an empty side of the book as a zero price.
+pub fn chase_price(is_buy: bool, best_bid: Decimal, best_ask: Decimal) -> Option<Decimal> {
+    let price = if is_buy { best_bid } else { best_ask };
+    if price.is_zero() { None } else { Some(price) }
+}
+
+impl AevoClient {
+    async fn place_child(&self, algo: &AlgoHandle, child: ChildOrder) -> Result<WorkingOrder> {
+        let order_id = self.create_order(
+            child.instrument_id, child.is_buy, to_f64(child.price)?, to_f64(child.amount)?, Some(true), Some(self.replies.next_id()), child.mmp
+        ).await?;
+        algo.push_child(&order_id);
+        Ok(WorkingOrder { order_id, price : child.price, replaces : None })
+    }
+
+    async fn replace_child(&self, algo: &AlgoHandle, working: &WorkingOrder, child: ChildOrder) -> Result<WorkingOrder> {
+        let order_id = self.edit_order(
+            working.order_id.clone(), child.instrument_id, child.is_buy, to_f64(child.price)?, to_f64(child.amount)?, Some(self.replies.next_id()), Some(true), child.mmp
+        ).await?;
+        algo.push_child(&order_id);
+        Ok(WorkingOrder { order_id, price : child.price, replaces : Some(Box::new(working.clone())) })
+    }
+
+    // Cancels the working order, and the order it replaces in case the edit gets rejected
+    async fn pull_child(&self, working: &mut Option<WorkingOrder>) {
+        if let Some(order) = working.take() {
+            if let Err(e) = self.cancel_order(order.order_id.clone()).await {
+                error!("Problem cancelling child order {}: {}", order.order_id, e);
+            }
+            if let Some(replaced) = order.replaces {
+                if let Err(e) = self.cancel_order(replaced.order_id.clone()).await {
+                    warn!("Problem cancelling replaced child order {}: {}", replaced.order_id, e);
+                }
+            }
+        }
+    }
+
+    // Shows `visible` of the quantity at a fixed price. A partially filled slice is topped back up
+    // with edit_order, a filled slice is followed by a new one. Pausing pulls the slice.
+    // read_messages has to be running for fills to be seen.
+    pub fn spawn_iceberg(client: Arc<AevoClient>, params: IcebergParams) -> (AlgoHandle, JoinHandle<()>) {
+        let handle = AlgoHandle::new(client.clone(), params.quantity);
+        let algo = handle.clone();
+
+        let task = tokio::spawn(async move {
+            info!("Starting iceberg for {} showing {} on instrument {}", params.quantity, params.visible, params.instrument_id);
+            let mut order_events = client.orders.lock().await.subscribe();
+            let mut control = tokio::time::interval(CONTROL_INTERVAL);
+            let mut working: Option<WorkingOrder> = None;
+
+            loop {
+                match algo.status() {
+                    AlgoStatus::Running => {},
+                    AlgoStatus::Paused => client.pull_child(&mut working).await,
+                    _ => {
+                        client.pull_child(&mut working).await;
+                        break
+                    }
+                }
+
+                let remaining = algo.progress().await.remaining();
+                if remaining.is_zero() {
+                    algo.set_status(AlgoStatus::Completed);
+                    break
+                }
+
+                tokio::select! {
+                    _ = control.tick() => {
+                        if working.is_none() && algo.status() == AlgoStatus::Running {
+                            let slice = params.visible.min(remaining);
+                            match client.place_child(&algo, params.child(slice)).await {
+                                Ok(order) => working = Some(order),
+                                Err(e) => error!("Problem placing iceberg slice: {}", e)
+                            }
+                        }
+                    },
+                    Some(event) = order_events.recv() => {
+                        if !algo.is_child(&event.order.order_id) {
+                            continue;
+                        }
+                        algo.notify(&event);
+
+                        let is_working = working.as_ref().is_some_and(|order| order.order_id == event.order.order_id);
+                        working = follow_child(working.take(), &event.order.order_id, event.order.state);
+
+                        if let (true, OrderState::PartiallyFilled, Some(current)) = (is_working, event.order.state, working.clone()) {
+                            let remaining = algo.progress().await.remaining();
+                            let slice = params.visible.min(remaining);
+                            match client.replace_child(&algo, &current, params.child(slice)).await {
+                                Ok(order) => working = Some(order),
+                                Err(e) => error!("Problem replenishing iceberg slice: {}", e)
+                            }
+                        }
+                    }
+                }
+            }
+
+            let progress = algo.progress().await;
+            info!("Iceberg on instrument {} finished {:?} with {} of {} filled", params.instrument_id, progress.status, progress.filled, progress.quantity);
+        });
+
+        (handle, task)
+    }
+
+    // Rests post-only at the best bid or ask and follows the touch until filled. Stops with
+    // LimitReached once following would move it more than max_slippage from the starting touch.
+    // Needs subscribe_book_ticker for the instrument and a running read_messages.
+    pub fn spawn_chase(client: Arc<AevoClient>, params: ChaseParams) -> (AlgoHandle, JoinHandle<()>) {
+        let handle = AlgoHandle::new(client.clone(), params.quantity);
+        let algo = handle.clone();
+
+        let task = tokio::spawn(async move {
+            info!("Starting chase for {} on instrument {}", params.quantity, params.instrument_id);
+            let mut order_events = client.orders.lock().await.subscribe();
+            let mut market_events = client.events.subscribe();
+            let mut control = tokio::time::interval(CONTROL_INTERVAL);
+            let mut working: Option<WorkingOrder> = None;
+            let mut touch: Option<Decimal> = None;
+            let mut limit: Option<Decimal> = None;
+
+            loop {
+                match algo.status() {
+                    AlgoStatus::Running => {},
+                    AlgoStatus::Paused => client.pull_child(&mut working).await,
+                    _ => {
+                        client.pull_child(&mut working).await;
+                        break
+                    }
+                }
+
+                let remaining = algo.progress().await.remaining();
+                if remaining.is_zero() {
+                    algo.set_status(AlgoStatus::Completed);
+                    break
+                }
+
+                tokio::select! {
+                    _ = control.tick() => {},
+                    Some(event) = order_events.recv() => {
+                        if !algo.is_child(&event.order.order_id) {
+                            continue;
+                        }
+                        algo.notify(&event);
+
+                        working = follow_child(working.take(), &event.order.order_id, event.order.state);
+                        continue;
+                    },
+                    Ok(event) = market_events.recv() => {
+                        if let WsResponse::SubscribeResponse { data : WsResponseData::BookTickerData { tickers, .. }, .. } = event {
+                            if let Some(ticker) = tickers.iter().find(|t| t.instrument_id == params.instrument_id) {
+                                touch = chase_price(params.is_buy, ticker.bid.price, ticker.ask.price).or(touch);
+                            }
+                        }
+                    }
+                }
+
+                let price = match touch {
+                    Some(price) => price,
+                    None => continue
+                };
+                let limit = *limit.get_or_insert(if params.is_buy { price + params.max_slippage } else { price - params.max_slippage });
+
+                if more_aggressive(params.is_buy, price, limit) {
+                    warn!("Chase on instrument {} reached its limit {} at {}", params.instrument_id, limit, price);
+                    client.pull_child(&mut working).await;
+                    algo.set_status(AlgoStatus::LimitReached);
+                    break
+                }
+
+                if algo.status() != AlgoStatus::Running {
+                    continue;
+                }
+
+                let result = match &working {
+                    None => client.place_child(&algo, params.child(price, remaining)).await,
+                    // Waits for a pending edit before moving the order again
+                    Some(order) if order.replaces.is_none() && more_aggressive(params.is_buy, price, order.price) => {
+                        client.replace_child(&algo, order, params.child(price, remaining)).await
+                    },
+                    Some(_) => continue
+                };
+                match result {
+                    Ok(order) => working = Some(order),
+                    Err(e) => error!("Problem placing chase order: {}", e)
+                }
+            }
+
+            let progress = algo.progress().await;
+            info!("Chase on instrument {} finished {:?} with {} of {} filled", params.instrument_id, progress.status, progress.filled, progress.quantity);
+        });
+
+        (handle, task)
+    }
+}
@@ -0,0 +1,51 @@
+use chrono::{DateTime, Utc};
+use serde_with::{formats::Flexible, TimestampNanoSeconds};
+
+pub use rust_decimal::Decimal;
+
+// Aevo encodes every timestamp as a string of nanoseconds since the epoch
+pub type Timestamp = DateTime<Utc>;
+
+pub type NanosStr = TimestampNanoSeconds<String, Flexible>;
+
+// Migration helper for code written against the string based models: returns the value
+// formatted the same way the exchange sends it on the wire
+pub trait LegacyString {
+    fn to_legacy_string(&self) -> String;
+}
+
+impl LegacyString for Decimal {
+    fn to_legacy_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl LegacyString for u64 {
+    fn to_legacy_string(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl LegacyString for Timestamp {
+    fn to_legacy_string(&self) -> String {
+        match self.timestamp_nanos_opt() {
+            Some(nanos) => nanos.to_string(),
+            None => self.timestamp().to_string()
+        }
+    }
+}
+
+impl LegacyString for String {
+    fn to_legacy_string(&self) -> String {
+        self.clone()
+    }
+}
+
+impl<T: LegacyString> LegacyString for Option<T> {
+    fn to_legacy_string(&self) -> String {
+        match self {
+            Some(value) => value.to_legacy_string(),
+            None => String::new()
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use serde_derive::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use crate::{credentials::Secret, types::{Decimal, NanosStr, Timestamp}};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct WsRequest {
@@ -15,7 +17,7 @@ pub struct Ping {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum WsRequestData {
-    AuthData {key : String, secret : String}, 
+    AuthData {key : String, secret : Secret}, 
     Ping (String),
     ChannelData (Vec<String>),
     OrderData {
@@ -46,24 +48,40 @@ pub enum WsRequestData {
     CancelOrderData {
         order_id : String
     }, 
-    CancelAllOrdersData {}
+    CancelAllOrdersData {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        instrument_type : Option<String>, 
+        #[serde(skip_serializing_if = "Option::is_none")]
+        asset : Option<String>, 
+        #[serde(skip_serializing_if = "Option::is_none")]
+        instrument : Option<String>
+    }
 } 
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum WsResponse {
     SubscribeResponse {
         channel : String,
-        write_ts : Option<String>, 
+        #[serde_as(as = "Option<NanosStr>")]
+        write_ts : Option<Timestamp>, 
         data : WsResponseData
     }, 
     PublishResponse {
-        id : Option<String>, 
+        #[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+        id : Option<u64>, 
         data : WsResponseData
     },
+    ErrorResponse {
+        #[serde_as(as = "Option<PickFirst<(_, DisplayFromStr)>>")]
+        id : Option<u64>, 
+        error : String
+    },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum WsResponseData {
     CancelAllOrdersData {
@@ -77,22 +95,26 @@ pub enum WsResponseData {
     CreateEditOrderData {
         order_id : String, 
         account : String, 
-        instrument_id : String, 
+        #[serde_as(as = "DisplayFromStr")]
+        instrument_id : u64, 
         instrument_name : String, 
         instrument_type : String, 
-        expiry : Option<String>, 
-        strike : Option<String>, 
+        #[serde_as(as = "Option<NanosStr>")]
+        expiry : Option<Timestamp>, 
+        strike : Option<Decimal>, 
         option_type : Option<String>, 
         order_type : String, 
         order_status : String, 
         side : String, 
-        amount : String, 
-        price : String, 
-        filled : String, 
-        initial_margin : String, 
-        avg_price : Option<String>, 
-        created_timestamp : String, 
-        timestamp : String, 
+        amount : Decimal, 
+        price : Decimal, 
+        filled : Decimal, 
+        initial_margin : Decimal, 
+        avg_price : Option<Decimal>, 
+        #[serde_as(as = "NanosStr")]
+        created_timestamp : Timestamp, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp, 
         system_type : String
     }, 
     StatusData {
@@ -101,144 +123,191 @@ pub enum WsResponseData {
     }, 
     PingData {
         success : bool, 
-        timestamp : String
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp
     }, 
     OrderBookData {
         r#type : String, 
-        instrument_id : String, 
+        #[serde_as(as = "DisplayFromStr")]
+        instrument_id : u64, 
         instrument_name : String, 
         instrument_type : String, 
-        bids : Vec<Vec<String>>, 
-        asks : Vec<Vec<String>>,
-        last_updated : String, 
+        bids : Vec<Vec<Decimal>>, 
+        asks : Vec<Vec<Decimal>>,
+        #[serde_as(as = "NanosStr")]
+        last_updated : Timestamp, 
         checksum : String
     },
     IndexData {
-        price : String, 
-        timestamp : String
+        price : Decimal, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp
     }, 
     OrdersData {
-        timestamp : String, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp, 
         orders : Vec<Order>
     }, 
     FillsData {
-        timestamp : String, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp, 
         fill : Fill
     }, 
     PositionsData {
-        timestamp : String, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp, 
         positions : Vec<Position>
     }, 
     TradesData {
         trade_id : String, 
-        instrument_id : String, 
+        #[serde_as(as = "DisplayFromStr")]
+        instrument_id : u64, 
         instrument_name : String, 
         instrument_type : String, 
         side : String, 
-        price : String, 
-        amount : Option<String>, 
-        created_timestamp : String
+        price : Decimal, 
+        amount : Option<Decimal>, 
+        #[serde_as(as = "NanosStr")]
+        created_timestamp : Timestamp
     }, 
     TickerData {
-        timestamp : String, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp, 
         tickers : Vec<Ticker>
     }, 
     BookTickerData {
-        timestamp : String, 
+        #[serde_as(as = "NanosStr")]
+        timestamp : Timestamp, 
         tickers : Vec<BookTicker>
-    }
+    }, 
+    MmpData (MmpEvent)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Position {
-    pub instrument_id : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
     pub instrument_name : String, 
     pub instrument_type : String, 
-    pub amount : String, 
-    pub mark_price : String, 
+    pub amount : Decimal, 
+    pub mark_price : Decimal, 
     pub option : Option<OptionData>, 
     pub asset : String, 
     pub side : String, 
-    pub avg_entry_price : String, 
-    pub unrealized_pnl : String, 
-    pub maintenance_margin : String
+    pub avg_entry_price : Decimal, 
+    pub unrealized_pnl : Decimal, 
+    pub maintenance_margin : Decimal
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct OptionData {
-    pub strike : String, 
+    pub strike : Decimal, 
     pub option_type : String, 
-    pub expiry : String, 
-    pub iv : String, 
-    pub delta : String, 
-    pub theta : String, 
-    pub rho : String, 
-    pub vega : String
+    #[serde_as(as = "NanosStr")]
+    pub expiry : Timestamp, 
+    pub iv : Decimal, 
+    pub delta : Decimal, 
+    pub theta : Decimal, 
+    pub rho : Decimal, 
+    pub vega : Decimal
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Fill {
     pub trade_id : String, 
     pub order_id : String, 
-    pub instrument_id : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
     pub instrument_name : String, 
     pub instrument_type : String, 
-    pub price : String, 
+    pub price : Decimal, 
     pub side : String, 
-    pub fees : String, 
-    pub filled : String, 
+    pub fees : Decimal, 
+    pub filled : Decimal, 
     pub order_status : String, 
     pub liquidity : String, 
-    pub created_timestamp : String, 
+    #[serde_as(as = "NanosStr")]
+    pub created_timestamp : Timestamp, 
     pub system_type : String
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Order {
     pub order_id : String, 
     pub account : String, 
-    pub instrument_id : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
     pub instrument_name : String, 
     pub instrument_type : String, 
     pub order_type : String, 
     pub side : String, 
-    pub price : String, 
-    pub amount : String, 
-    pub filled : String, 
+    pub price : Decimal, 
+    pub amount : Decimal, 
+    pub filled : Decimal, 
     pub order_status : String, 
-    pub created_timestamp : String, 
+    #[serde_as(as = "NanosStr")]
+    pub created_timestamp : Timestamp, 
     pub system_type : String
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BookTicker {
-    pub instrument_id : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
     pub instrument_name : String, 
     pub instrument_type : String, 
     pub bid : PriceLevel, 
     pub ask : PriceLevel
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Ticker {
-    pub instrument_id : String, 
+    #[serde_as(as = "DisplayFromStr")]
+    pub instrument_id : u64, 
     pub instrument_name : String, 
     pub instrument_type : String, 
-    pub funding_rate : String, 
-    pub next_funding_rate : String, 
+    pub funding_rate : Decimal, 
+    pub next_funding_rate : Decimal, 
     pub mark : PriceLevel, 
     pub  bid : PriceLevel, 
     pub ask : PriceLevel
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PriceLevel {
-    pub price : String, 
-    pub delta : Option<String>, 
-    pub theta : Option<String>, 
-    pub gamma : Option<String>, 
-    pub rho : Option<String>, 
-    pub vega : Option<String>, 
-    pub iv : Option<String>, 
-    pub amount : Option<String>
+    pub price : Decimal, 
+    pub delta : Option<Decimal>, 
+    pub theta : Option<Decimal>, 
+    pub gamma : Option<Decimal>, 
+    pub rho : Option<Decimal>, 
+    pub vega : Option<Decimal>, 
+    pub iv : Option<Decimal>, 
+    pub amount : Option<Decimal>
+}
+
+// Sent on the mmp channel when market maker protection freezes or unfreezes an asset
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MmpEvent {
+    pub asset : String, 
+    pub frozen : bool, 
+    #[serde_as(as = "Option<NanosStr>")]
+    #[serde(default)]
+    pub frozen_end_time : Option<Timestamp>, 
+    #[serde(default)]
+    pub reason : Option<String>
+}
+
+// A websocket message as received, kept next to its decoded form for auditing
+#[derive(Debug, Clone)]
+pub struct RawWsResponse {
+    pub raw : String, 
+    pub received : Timestamp, 
+    pub response : WsResponse
 }